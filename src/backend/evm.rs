@@ -0,0 +1,96 @@
+use anyhow::{anyhow, bail};
+use bytes::Bytes;
+use revm::{return_ok, InMemoryDB, Return, TransactOut, TransactTo, EVM};
+
+/// Verify a proof with the project's verifier contract, as the chain does: the contract is deployed from
+/// `deployment_code` in an empty EVM and called with the instances as 32 bytes big endian words followed by the proof,
+/// the calldata of a snark-verifier generated verifier. The proof is valid if the call does not revert.
+pub fn verify(deployment_code: &[u8], instances: &[String], proof: &[u8]) -> anyhow::Result<()> {
+    let mut calldata = Vec::with_capacity(instances.len() * 32 + proof.len());
+    for instance in instances {
+        calldata.extend_from_slice(&word(instance)?);
+    }
+    calldata.extend_from_slice(proof);
+
+    let mut evm = EVM::new();
+    evm.database(InMemoryDB::default());
+    //generated verifiers are larger than the mainnet contract size limit
+    evm.env.cfg.limit_contract_code_size = Some(usize::MAX);
+    evm.env.tx.transact_to = TransactTo::create();
+    evm.env.tx.data = Bytes::copy_from_slice(deployment_code);
+    let deployed = evm.transact_commit();
+    let verifier = match (deployed.exit_reason, deployed.out) {
+        (return_ok!(), TransactOut::Create(_, Some(address))) => address,
+        (reason, _) => bail!("deploy the verifier contract: {:?}", reason),
+    };
+
+    evm.env.tx.transact_to = TransactTo::Call(verifier);
+    evm.env.tx.data = calldata.into();
+    let result = evm.transact_commit();
+    match result.exit_reason {
+        return_ok!() => Ok(()),
+        reason => Err(anyhow!("the verifier contract rejects the proof: {:?}", reason)),
+    }
+}
+
+//a decimal instance as a 32 bytes big endian word
+fn word(decimal: &str) -> anyhow::Result<[u8; 32]> {
+    let mut word = [0u8; 32];
+    for digit in decimal.bytes() {
+        if !digit.is_ascii_digit() {
+            bail!("instance is not a decimal: {:?}", decimal);
+        }
+        let mut carry = (digit - b'0') as u32;
+        for byte in word.iter_mut().rev() {
+            let value = *byte as u32 * 10 + carry;
+            *byte = value as u8;
+            carry = value >> 8;
+        }
+        if carry != 0 {
+            bail!("instance does not fit in 32 bytes: {:?}", decimal);
+        }
+    }
+    Ok(word)
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Deploys a toy verifier that accepts 3 words of calldata, two instances and a proof equal to their sum.
+    pub const SUM_VERIFIER: &str = concat!(
+        //copy the 29 bytes of runtime code after this header to memory and return them
+        "601d600c600039601d6000f3",
+        //revert unless the calldata is 96 bytes and word 0 + word 1 == word 2
+        "60603614156016576000356020350160403514601b575b600080fd5b00",
+    );
+
+    pub fn sum_proof(a: u64, b: u64) -> Vec<u8> {
+        word(&(a + b).to_string()).unwrap().to_vec()
+    }
+
+    #[test]
+    fn instances_as_words() {
+        assert_eq!(word("0").unwrap(), [0; 32]);
+        let mut expected = [0u8; 32];
+        expected[24..].copy_from_slice(&12345678901234567890u64.to_be_bytes());
+        assert_eq!(word("12345678901234567890").unwrap(), expected);
+        assert!(word(&"9".repeat(78)).is_err());
+        assert!(word("0x01").is_err());
+    }
+
+    #[test]
+    fn verify_with_contract() {
+        let code = hex::decode(SUM_VERIFIER).unwrap();
+        let instances = vec!["12345678901234567890".to_string(), "7".to_string()];
+        let proof = sum_proof(12345678901234567890, 7);
+        verify(&code, &instances, &proof).unwrap();
+
+        let mut tampered = proof.clone();
+        tampered[31] ^= 1;
+        assert!(verify(&code, &instances, &tampered).is_err());
+        assert!(verify(&code, &["12345678901234567890".to_string(), "8".to_string()], &proof).is_err());
+        //not a contract
+        assert!(verify(&[0xfe], &instances, &proof).is_err());
+    }
+}
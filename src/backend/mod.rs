@@ -0,0 +1,260 @@
+mod evm;
+mod taiko_a5;
+pub mod worker;
+
+use std::{any::Any, fmt, sync::Arc};
+
+use anyhow::bail;
+use futures::future::BoxFuture;
+use taiko_stratum::proof::{self, Proof};
+use tokio_util::sync::CancellationToken;
+
+use crate::prover::ProjectInfo;
+use crate::scheduler::TaskCost;
+
+/// Backend specific task inputs, produced by `ProofBackend::parse`.
+pub type TaskInput = Box<dyn Any + Send>;
+
+/// Proof produced by a backend, independent of the project's prover core types.
+#[derive(Debug, Clone, Default)]
+pub struct ProofOutput {
+    /// public instances in decimal
+    pub instances: Vec<String>,
+    pub proof: Vec<u8>,
+    pub k: u8,
+}
+
+/// The proof was stopped because its task was cancelled.
+#[derive(Debug)]
+pub struct Cancelled;
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cancelled")
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+/// Order of the BN254 scalar field, public instances are elements of it.
+const BN254_SCALAR_MODULUS: &str = "21888242871839275222246405745257275088548364400416034343698204186575808495617";
+
+/// Check the shape of a proof: public instances that are field elements in canonical decimal,
+/// and a transcript of whole 32 bytes words. This does not tell whether the proof is valid.
+pub fn check_shape(output: &ProofOutput) -> anyhow::Result<()> {
+    if output.instances.is_empty() {
+        bail!("proof has no public instances");
+    }
+    for (i, instance) in output.instances.iter().enumerate() {
+        let canonical = !instance.is_empty()
+            && instance.bytes().all(|c| c.is_ascii_digit())
+            && (instance == "0" || !instance.starts_with('0'));
+        let in_field = instance.len() < BN254_SCALAR_MODULUS.len()
+            || (instance.len() == BN254_SCALAR_MODULUS.len() && instance.as_str() < BN254_SCALAR_MODULUS);
+        if !canonical || !in_field {
+            bail!("instance {} is not a field element: {:?}", i, instance);
+        }
+    }
+    if output.proof.is_empty() || output.proof.len() % 32 != 0 {
+        bail!("proof of {} bytes is not a transcript of 32 bytes words", output.proof.len());
+    }
+    Ok(())
+}
+
+/// Outcome of checking a proof before it is submitted.
+#[derive(Debug)]
+pub enum ProofCheck {
+    /// well formed and accepted by the backend's verifier
+    Verified,
+    /// well formed, the backend has no verifier
+    Unverified,
+    /// not well formed, see `check_shape`
+    Malformed(anyhow::Error),
+    /// rejected by the backend's verifier
+    Rejected(anyhow::Error),
+}
+
+/// Check the shape of a proof, then verify it if the backend can.
+pub fn check(project: &ProjectInfo, task_id: u64, output: &ProofOutput) -> ProofCheck {
+    if let Err(e) = check_shape(output) {
+        return ProofCheck::Malformed(e);
+    }
+    match project.backend.verify(project, task_id, output) {
+        Some(Ok(())) => ProofCheck::Verified,
+        Some(Err(e)) => ProofCheck::Rejected(e),
+        None => ProofCheck::Unverified,
+    }
+}
+
+/// Stop between proving phases once the task is cancelled.
+pub fn check_cancelled(cancel: &CancellationToken) -> anyhow::Result<()> {
+    if cancel.is_cancelled() {
+        return Err(Cancelled.into());
+    }
+    Ok(())
+}
+
+/// One zkp project supported by the prover.
+pub trait ProofBackend: Send + Sync {
+    /// Project name as sent by the pool in `zkpool.notify`.
+    fn name(&self) -> &'static str;
+
+    /// Resources a task holds while proving, unless the project config overrides them.
+    fn default_cost(&self) -> TaskCost {
+        TaskCost { cpu: 1, memory_gb: 0 }
+    }
+
+    /// Parse the task content received from the pool.
+    fn parse(&self, task_id: u64, task_content: &str) -> anyhow::Result<TaskInput>;
+
+    /// Compute the proof of a parsed task on a prover worker thread.
+    /// Long proofs should call `check_cancelled` between phases and return `Cancelled` once `cancel` is set,
+    /// or run in a worker process, see `worker::prove_in_child`.
+    fn prove(
+        &self,
+        project: &ProjectInfo,
+        task_id: u64,
+        input: TaskInput,
+        cancel: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<ProofOutput>>;
+
+    /// Verify a well formed proof against its public instances before it is submitted, for projects with `verify` set.
+    /// None if the backend has no verifier.
+    fn verify(&self, _project: &ProjectInfo, _task_id: u64, _output: &ProofOutput) -> Option<anyhow::Result<()>> {
+        None
+    }
+
+    /// Encode the proof for `zkpool.submit` in the versioned format of `taiko_stratum::proof`,
+    /// the client falls back to the legacy format for pools without proof-v1.
+    fn serialize(&self, output: &ProofOutput) -> String {
+        proof::encode(&Proof {
+            instances: output.instances.clone(),
+            proof: output.proof.clone(),
+        })
+    }
+}
+
+/// Prove a task in this process, for the `prove-worker` subcommand.
+pub async fn prove_here(request: &worker::WorkerRequest) -> anyhow::Result<ProofOutput> {
+    match request.project.as_str() {
+        "taikoA5" => {
+            let task = request.task_content.parse()?;
+            taiko_a5::prove_here(request.rpc_url.clone(), request.task_id, task).await
+        }
+        project => bail!("project {} has no worker", project),
+    }
+}
+
+/// All backends compiled into this prover, add new projects here.
+fn builtin() -> Vec<Arc<dyn ProofBackend>> {
+    vec![Arc::new(taiko_a5::TaikoA5)]
+}
+
+/// Find the backend serving `project_name`.
+pub fn lookup(project_name: &str) -> Option<Arc<dyn ProofBackend>> {
+    builtin().into_iter().find(|backend| backend.name() == project_name)
+}
+
+/// Names of all built-in backends.
+pub fn names() -> Vec<&'static str> {
+    builtin().iter().map(|backend| backend.name()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use tokio::{runtime::Handle, time::timeout};
+
+    use super::*;
+    use crate::pool::Pool;
+
+    /// Burns cpu in phases until cancelled, like a real proof without awaits.
+    struct SpinBackend;
+
+    impl ProofBackend for SpinBackend {
+        fn name(&self) -> &'static str {
+            "spin"
+        }
+
+        fn parse(&self, _task_id: u64, _task_content: &str) -> anyhow::Result<TaskInput> {
+            Ok(Box::new(()))
+        }
+
+        fn prove(
+            &self,
+            _project: &ProjectInfo,
+            _task_id: u64,
+            _input: TaskInput,
+            cancel: CancellationToken,
+        ) -> BoxFuture<'static, anyhow::Result<ProofOutput>> {
+            Box::pin(async move {
+                let mut acc = 0u64;
+                loop {
+                    check_cancelled(&cancel)?;
+                    let phase = Instant::now();
+                    while phase.elapsed() < Duration::from_millis(10) {
+                        acc = acc.wrapping_mul(31).wrapping_add(1);
+                    }
+                    std::hint::black_box(acc);
+                }
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn cancelled_proof_stops() {
+        let backend = Arc::new(SpinBackend);
+        let project = ProjectInfo {
+            name: "spin".to_string(),
+            rpc_url: String::new(),
+            backend: backend.clone(),
+            cost: backend.default_cost(),
+            verify: false,
+            verifier: None,
+        };
+        let pool = Pool::new(1, Handle::current());
+        let cancel = CancellationToken::new();
+        let input = backend.parse(1, "").unwrap();
+        let result = pool.spawn(backend.prove(&project, 1, input, cancel.clone()));
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancel.cancel();
+        let result = timeout(Duration::from_secs(1), result)
+            .await
+            .expect("cancelled proof must stop in time")
+            .unwrap();
+        assert!(result.unwrap_err().is::<Cancelled>());
+    }
+
+    #[test]
+    fn check_proof_shape() {
+        let backend = Arc::new(SpinBackend);
+        let project = ProjectInfo {
+            name: "spin".to_string(),
+            rpc_url: String::new(),
+            backend: backend.clone(),
+            cost: backend.default_cost(),
+            verify: true,
+            verifier: None,
+        };
+        let output = ProofOutput {
+            instances: vec!["0".to_string(), BN254_SCALAR_MODULUS.replace("617", "616")],
+            proof: vec![7; 64],
+            k: 20,
+        };
+        //the spin backend has no verifier
+        assert!(matches!(check(&project, 1, &output), ProofCheck::Unverified));
+
+        let mut invalid = output.clone();
+        invalid.instances[1] = BN254_SCALAR_MODULUS.to_string();
+        assert!(matches!(check(&project, 1, &invalid), ProofCheck::Malformed(_)));
+        let mut invalid = output.clone();
+        invalid.instances[0] = "0x01".to_string();
+        assert!(check_shape(&invalid).is_err());
+        let mut invalid = output;
+        invalid.proof.pop();
+        assert!(check_shape(&invalid).is_err());
+        assert!(check_shape(&ProofOutput::default()).is_err());
+    }
+}
@@ -0,0 +1,295 @@
+use std::{fmt, str::FromStr};
+
+use anyhow::anyhow;
+use futures::future::BoxFuture;
+use tokio_util::sync::CancellationToken;
+
+//taiko A5 testnet lib core
+use prover::shared_state::generate_proof;
+
+use crate::prover::ProjectInfo;
+use crate::scheduler::TaskCost;
+
+use super::{
+    check_cancelled, evm,
+    worker::{self, WorkerRequest},
+    ProofBackend, ProofOutput, TaskInput,
+};
+
+pub struct TaikoA5;
+
+/// Inputs of one Taiko A5 block proof, as `#` separated in `zkpool.notify`.
+pub struct TaikoA5Task {
+    pub prover_address: String,
+    pub l1_signal_service: String,
+    pub l2_signal_service: String,
+    pub taiko_l2: String,
+    pub meta_hash: String,
+    pub block_hash: String,
+    pub parent_hash: String,
+    pub signal_root: String,
+    pub graffiti: String,
+    pub gas_used: u64,
+    pub parent_gas_used: u64,
+    pub block_max_gas_limit: u64,
+    pub max_transactions_per_block: u64,
+    pub max_bytes_per_tx_list: u64,
+}
+
+const TASK_FIELD_NUM: usize = 14;
+
+impl fmt::Display for TaikoA5Task {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}#{}#{}#{}#{}#{}#{}#{}#{}#{}#{}#{}#{}#{}",
+            self.prover_address,
+            self.l1_signal_service,
+            self.l2_signal_service,
+            self.taiko_l2,
+            self.meta_hash,
+            self.block_hash,
+            self.parent_hash,
+            self.signal_root,
+            self.graffiti,
+            self.gas_used,
+            self.parent_gas_used,
+            self.block_max_gas_limit,
+            self.max_transactions_per_block,
+            self.max_bytes_per_tx_list
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum TaskParseError {
+    FieldCount(usize),
+    Address { field: &'static str, value: String },
+    Hash { field: &'static str, value: String },
+    Number { field: &'static str, value: String },
+    OutOfRange { field: &'static str, value: u64, limit: &'static str },
+}
+
+impl fmt::Display for TaskParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskParseError::FieldCount(n) => write!(f, "expect {} task fields, got {}", TASK_FIELD_NUM, n),
+            TaskParseError::Address { field, value } => write!(f, "{} is not a 20 bytes hex address: {:?}", field, value),
+            TaskParseError::Hash { field, value } => write!(f, "{} is not a 32 bytes hex hash: {:?}", field, value),
+            TaskParseError::Number { field, value } => write!(f, "{} is not an unsigned integer: {:?}", field, value),
+            TaskParseError::OutOfRange { field, value, limit } => write!(f, "{} {} out of range, {}", field, value, limit),
+        }
+    }
+}
+
+impl std::error::Error for TaskParseError {}
+
+fn parse_hex(field: &'static str, value: &str, len: usize) -> Result<String, TaskParseError> {
+    let raw = value.strip_prefix("0x").unwrap_or(value);
+    match hex::decode(raw) {
+        Ok(bytes) if bytes.len() == len => Ok(value.to_string()),
+        _ if len == 20 => Err(TaskParseError::Address { field, value: value.to_string() }),
+        _ => Err(TaskParseError::Hash { field, value: value.to_string() }),
+    }
+}
+
+fn parse_u64(field: &'static str, value: &str) -> Result<u64, TaskParseError> {
+    value.parse::<u64>().map_err(|_| TaskParseError::Number { field, value: value.to_string() })
+}
+
+impl FromStr for TaikoA5Task {
+    type Err = TaskParseError;
+
+    fn from_str(task_content: &str) -> Result<Self, Self::Err> {
+        let task_vec: Vec<&str> = task_content.split('#').collect();
+        if task_vec.len() != TASK_FIELD_NUM {
+            return Err(TaskParseError::FieldCount(task_vec.len()));
+        }
+        let task = TaikoA5Task {
+            prover_address: parse_hex("prover_address", task_vec[0], 20)?,
+            l1_signal_service: parse_hex("l1_signal_service", task_vec[1], 20)?,
+            l2_signal_service: parse_hex("l2_signal_service", task_vec[2], 20)?,
+            taiko_l2: parse_hex("taiko_l2", task_vec[3], 20)?,
+            meta_hash: parse_hex("meta_hash", task_vec[4], 32)?,
+            block_hash: parse_hex("block_hash", task_vec[5], 32)?,
+            parent_hash: parse_hex("parent_hash", task_vec[6], 32)?,
+            signal_root: parse_hex("signal_root", task_vec[7], 32)?,
+            graffiti: parse_hex("graffiti", task_vec[8], 32)?,
+            gas_used: parse_u64("gas_used", task_vec[9])?,
+            parent_gas_used: parse_u64("parent_gas_used", task_vec[10])?,
+            block_max_gas_limit: parse_u64("block_max_gas_limit", task_vec[11])?,
+            max_transactions_per_block: parse_u64("max_transactions_per_block", task_vec[12])?,
+            max_bytes_per_tx_list: parse_u64("max_bytes_per_tx_list", task_vec[13])?,
+        };
+
+        // gas used is a uint32 in the taiko protocol
+        if task.gas_used > u32::MAX as u64 {
+            return Err(TaskParseError::OutOfRange { field: "gas_used", value: task.gas_used, limit: "must fit in uint32" });
+        }
+        if task.parent_gas_used > u32::MAX as u64 {
+            return Err(TaskParseError::OutOfRange { field: "parent_gas_used", value: task.parent_gas_used, limit: "must fit in uint32" });
+        }
+        if task.block_max_gas_limit == 0 {
+            return Err(TaskParseError::OutOfRange { field: "block_max_gas_limit", value: 0, limit: "must be positive" });
+        }
+        if task.gas_used > task.block_max_gas_limit {
+            return Err(TaskParseError::OutOfRange { field: "gas_used", value: task.gas_used, limit: "must not exceed block_max_gas_limit" });
+        }
+        if task.max_transactions_per_block == 0 {
+            return Err(TaskParseError::OutOfRange { field: "max_transactions_per_block", value: 0, limit: "must be positive" });
+        }
+        if task.max_bytes_per_tx_list == 0 {
+            return Err(TaskParseError::OutOfRange { field: "max_bytes_per_tx_list", value: 0, limit: "must be positive" });
+        }
+        Ok(task)
+    }
+}
+
+impl ProofBackend for TaikoA5 {
+    fn name(&self) -> &'static str {
+        "taikoA5"
+    }
+
+    fn default_cost(&self) -> TaskCost {
+        //see the hardware requirements in the README
+        TaskCost { cpu: 8, memory_gb: 32 }
+    }
+
+    fn parse(&self, _task_id: u64, task_content: &str) -> anyhow::Result<TaskInput> {
+        Ok(Box::new(task_content.parse::<TaikoA5Task>()?))
+    }
+
+    fn prove(
+        &self,
+        project: &ProjectInfo,
+        task_id: u64,
+        input: TaskInput,
+        cancel: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<ProofOutput>> {
+        let project_name = self.name();
+        let rpc_url = project.rpc_url.clone();
+        Box::pin(async move {
+            let input = input
+                .downcast::<TaikoA5Task>()
+                .map_err(|_| anyhow!("task input is not a taikoA5 task"))?;
+            check_cancelled(&cancel)?;
+            //the prover core has no cancellation hook, the proof runs in a worker process that is killed on cancel
+            let request = WorkerRequest {
+                project: project_name.to_string(),
+                rpc_url,
+                task_id,
+                task_content: input.to_string(),
+            };
+            worker::prove_in_child(&request, cancel).await
+        })
+    }
+
+    //the aggregation proof is checked by the project's verifier contract, as on L1
+    fn verify(&self, project: &ProjectInfo, _task_id: u64, output: &ProofOutput) -> Option<anyhow::Result<()>> {
+        project
+            .verifier
+            .as_ref()
+            .map(|code| evm::verify(code, &output.instances, &output.proof))
+    }
+}
+
+/// Prove a block in this process, in a worker process of the prover.
+pub async fn prove_here(l2_rpc: String, task_id: u64, task: TaikoA5Task) -> anyhow::Result<ProofOutput> {
+    let agg_proof_result = generate_proof(
+        l2_rpc,
+        task_id,
+        task.prover_address,
+        task.l1_signal_service,
+        task.l2_signal_service,
+        task.taiko_l2,
+        task.meta_hash,
+        task.block_hash,
+        task.parent_hash,
+        task.signal_root,
+        task.graffiti,
+        task.gas_used,
+        task.parent_gas_used,
+        task.block_max_gas_limit,
+        task.max_transactions_per_block,
+        task.max_bytes_per_tx_list,
+    )
+    .await
+    .map_err(|e| anyhow!("generate proof of block {} failed: {:?}", task_id, e))?;
+    Ok(ProofOutput {
+        instances: agg_proof_result.instance.iter().map(|var| var.to_string()).collect(),
+        proof: agg_proof_result.proof.to_vec(),
+        k: agg_proof_result.k,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::backend::{self, evm::tests::sum_proof, evm::tests::SUM_VERIFIER, ProofCheck};
+
+    fn task_fields() -> Vec<String> {
+        let address = format!("0x{}", "11".repeat(20));
+        let hash = format!("0x{}", "22".repeat(32));
+        let mut fields = vec![address; 4];
+        fields.extend(vec![hash; 5]);
+        fields.extend(["1000", "900", "6000000", "79", "120000"].iter().map(|v| v.to_string()));
+        fields
+    }
+
+    #[test]
+    fn parse_valid_task() {
+        let task = task_fields().join("#").parse::<TaikoA5Task>().unwrap();
+        assert_eq!(task.gas_used, 1000);
+        assert_eq!(task.max_bytes_per_tx_list, 120000);
+        //as sent to the worker process
+        assert_eq!(task.to_string(), task_fields().join("#"));
+    }
+
+    #[test]
+    fn reject_malformed_fields() {
+        let mut fields = task_fields();
+        fields[9] = "12a".to_string();
+        let err = fields.join("#").parse::<TaikoA5Task>().err().unwrap();
+        assert!(matches!(err, TaskParseError::Number { field: "gas_used", .. }));
+
+        let mut fields = task_fields();
+        fields[2] = "0x1234".to_string();
+        let err = fields.join("#").parse::<TaikoA5Task>().err().unwrap();
+        assert!(matches!(err, TaskParseError::Address { field: "l2_signal_service", .. }));
+
+        let mut fields = task_fields();
+        fields[11] = "100".to_string();
+        let err = fields.join("#").parse::<TaikoA5Task>().err().unwrap();
+        assert!(matches!(err, TaskParseError::OutOfRange { field: "gas_used", .. }));
+
+        let err = "0x00#0x00".parse::<TaikoA5Task>().err().unwrap();
+        assert!(matches!(err, TaskParseError::FieldCount(2)));
+    }
+
+    #[test]
+    fn verify_with_project_verifier() {
+        let mut project = ProjectInfo {
+            name: "taikoA5".to_string(),
+            rpc_url: String::new(),
+            backend: Arc::new(TaikoA5),
+            cost: TaikoA5.default_cost(),
+            verify: true,
+            verifier: Some(Arc::new(hex::decode(SUM_VERIFIER).unwrap())),
+        };
+        let output = ProofOutput {
+            instances: vec!["40".to_string(), "2".to_string()],
+            proof: sum_proof(40, 2),
+            k: 20,
+        };
+        assert!(matches!(backend::check(&project, 1, &output), ProofCheck::Verified));
+
+        let mut tampered = output;
+        tampered.proof[31] ^= 1;
+        assert!(matches!(backend::check(&project, 1, &tampered), ProofCheck::Rejected(_)));
+
+        project.verifier = None;
+        assert!(matches!(backend::check(&project, 1, &tampered), ProofCheck::Unverified));
+    }
+}
@@ -0,0 +1,154 @@
+use std::{
+    io::{Read, Write},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, bail, Context};
+use clap::Args;
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use super::{Cancelled, ProofOutput};
+
+/// How often a running worker process is checked for its exit
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Prove one task in this process for a parent prover, reading the task as json from stdin
+/// and writing the outcome as json to stdout. The parent kills the process to cancel the proof.
+#[derive(Debug, Args)]
+pub struct WorkerOpt {}
+
+/// Task sent to a worker process, the rpc url stays off the command line.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct WorkerRequest {
+    pub project: String,
+    pub rpc_url: String,
+    pub task_id: u64,
+    pub task_content: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+enum WorkerResponse {
+    Proof {
+        instances: Vec<String>,
+        /// hex
+        proof: String,
+        k: u8,
+    },
+    Failed(String),
+}
+
+/// Kills the worker unless it has exited, so that no proof outlives its task.
+struct KillOnDrop(Child);
+
+impl Drop for KillOnDrop {
+    fn drop(&mut self) {
+        if let Ok(None) = self.0.try_wait() {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+}
+
+/// Run `command` with `input` on its stdin and return its stdout, killing it once `cancel` is set.
+pub async fn run(mut command: Command, input: &[u8], cancel: CancellationToken) -> anyhow::Result<Vec<u8>> {
+    let child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("start prover worker")?;
+    let mut child = KillOnDrop(child);
+    if let Some(mut stdin) = child.0.stdin.take() {
+        stdin.write_all(input).context("send the task to the prover worker")?;
+    }
+    //drained aside so that a large proof never blocks the worker on a full pipe
+    let mut stdout = child.0.stdout.take().ok_or_else(|| anyhow!("prover worker has no stdout"))?;
+    let reader = thread::spawn(move || {
+        let mut output = Vec::new();
+        stdout.read_to_end(&mut output).map(|_| output)
+    });
+    let status = loop {
+        if let Some(status) = child.0.try_wait()? {
+            break status;
+        }
+        tokio::select! {
+            _ = cancel.cancelled() => return Err(Cancelled.into()),
+            _ = tokio::time::sleep(POLL_INTERVAL) => {},
+        }
+    };
+    let output = reader.join().map_err(|_| anyhow!("prover worker output reader panicked"))??;
+    if !status.success() {
+        bail!("prover worker exited with {}", status);
+    }
+    Ok(output)
+}
+
+/// Prove `request` in a new process of this binary, see `serve`.
+pub async fn prove_in_child(request: &WorkerRequest, cancel: CancellationToken) -> anyhow::Result<ProofOutput> {
+    let mut command = Command::new(std::env::current_exe().context("locate the prover binary")?);
+    //the worker proves on as many threads as this process
+    command.args(["--threads", &rayon::current_num_threads().to_string(), "prove-worker"]);
+    let output = run(command, &serde_json::to_vec(request)?, cancel).await?;
+    match serde_json::from_slice(&output).context("read the prover worker output")? {
+        WorkerResponse::Proof { instances, proof, k } => Ok(ProofOutput {
+            instances,
+            proof: hex::decode(proof).context("read the prover worker proof")?,
+            k,
+        }),
+        WorkerResponse::Failed(e) => Err(anyhow!(e)),
+    }
+}
+
+/// Body of the `prove-worker` subcommand.
+pub async fn serve() -> anyhow::Result<()> {
+    let mut input = Vec::new();
+    std::io::stdin().read_to_end(&mut input).context("read the task")?;
+    let request: WorkerRequest = serde_json::from_slice(&input).context("read the task")?;
+    let response = match super::prove_here(&request).await {
+        Ok(output) => WorkerResponse::Proof {
+            instances: output.instances,
+            proof: hex::encode(output.proof),
+            k: output.k,
+        },
+        Err(e) => WorkerResponse::Failed(format!("{:#}", e)),
+    };
+    let mut stdout = std::io::stdout();
+    serde_json::to_writer(&mut stdout, &response)?;
+    stdout.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    fn sh(script: &str) -> Command {
+        let mut command = Command::new("sh");
+        command.args(["-c", script]);
+        command
+    }
+
+    #[tokio::test]
+    async fn run_worker_process() {
+        let output = run(sh("cat"), b"task", CancellationToken::new()).await.unwrap();
+        assert_eq!(output, b"task");
+        assert!(run(sh("exit 3"), b"", CancellationToken::new()).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn cancel_kills_the_worker() {
+        let cancel = CancellationToken::new();
+        let started = Instant::now();
+        let worker = tokio::spawn(run(sh("sleep 30"), b"", cancel.clone()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        cancel.cancel();
+        let result = worker.await.unwrap();
+        assert!(result.unwrap_err().is::<Cancelled>());
+        //the sleep was killed, not waited for
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+}
@@ -1,14 +1,18 @@
-use std::{sync::Arc, time::Duration};
-use std::sync::atomic::{AtomicBool};
+use std::{fmt, io, sync::Arc, time::{Duration, Instant}};
+use std::collections::{BTreeSet, HashMap};
+use std::sync::atomic::AtomicBool;
 
 use taiko_stratum::{
+    chunk,
     codec::StratumCodec,
-    message::StratumMessage,
+    message::{Capability, HeartbeatStatus, StratumMessage, PROTOCOL_VERSION},
 };
 use taiko_stratum::codec::ResponseParams;
+use taiko_stratum::proof::ProofFormat;
 use futures_util::sink::SinkExt;
-use json_rpc_types::Id;
+use json_rpc_types::{ErrorCode, Id};
 use tokio::{
+    io::{AsyncRead, AsyncWrite},
     net::TcpStream,
     sync::{
         mpsc,
@@ -19,28 +23,71 @@ use tokio::{
     time::{sleep, timeout},
 };
 use tokio_stream::StreamExt;
+use tokio_rustls::TlsConnector;
 use tokio_util::codec::Framed;
 use tracing::{error, info, warn, debug};
+use crate::endpoints::{PoolEndpoint, PoolEndpoints};
+use crate::host::{self, Hardware, HostLoad};
+use crate::message::{BinaryCodec, PoolCodec, DEFAULT_MAX_BINARY_FRAME_LENGTH};
+use crate::submit::{PendingSubmits, SubmitFormat, SubmitRecord, SUBMIT_MAX_ATTEMPTS};
+use crate::tls;
+use crate::outbox::Outbox;
 use crate::prover::ProverEvent;
-use crate::prover::LATEST_TASK_CONTENT;
-use crate::prover::TASK_HANDLER;
+use crate::prover::ProverState;
+use crate::scheduler::{TaskPhase, TaskState};
+
+/// Byte stream to a pool, plain TCP or TLS
+pub trait PoolStream: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> PoolStream for T {}
+
+/// Heartbeats without a response for this long are not counted in the round trip time
+const HEARTBEAT_RTT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Capabilities this prover implements, offered to the pool on subscribe
+const CAPABILITIES: [Capability; 4] = [
+    Capability::Progress,
+    Capability::ChunkedSubmit,
+    Capability::ProofV1,
+    Capability::InvalidProof,
+];
+
+/// Process exit code when the pool rejects the access key
+pub const AUTH_FAILED_EXIT_CODE: i32 = 2;
 
 pub struct Client {
     pub name: String ,
-    pub server: String,
+    pub endpoints: Mutex<PoolEndpoints>,
     pub uuid:String,
+    pub auth_backoff: Option<Duration>,
+    pub tls: Option<TlsConnector>,
+    pub submit_timeout: Duration,
+    pub disconnect_grace: Duration,
+    pub outbox: Outbox,
+    /// reported to the pool when subscribing
+    pub hardware: Hardware,
+    /// longest line read from or sent to the pool, longer submits go in chunks if the pool supports them
+    pub max_frame_length: usize,
     pub sender: Arc<Sender<StratumMessage>>,
     pub busy: Arc<AtomicBool>,
     pub receiver: Arc<Mutex<Receiver<StratumMessage>>>,
 }
 
 impl Client {
-    pub fn init(name: String, device_id:String,server: String) -> Arc<Self> {
+    #[allow(clippy::too_many_arguments)]
+    pub fn init(name: String, device_id:String,endpoints: PoolEndpoints, auth_backoff: Option<Duration>, tls: Option<TlsConnector>, submit_timeout: Duration, disconnect_grace: Duration, outbox: Outbox, hardware: Hardware, max_frame_length: usize) -> Arc<Self> {
         let (sender, receiver) = mpsc::channel(4096);
         Arc::new(Self {
             name,
-            server,
+            endpoints: Mutex::new(endpoints),
             uuid:device_id,
+            auth_backoff,
+            tls,
+            submit_timeout,
+            disconnect_grace,
+            outbox,
+            hardware,
+            max_frame_length,
             sender: Arc::new(sender),
             busy:  Arc::new(AtomicBool::new(false)),
             receiver: Arc::new(Mutex::new(receiver)),
@@ -56,28 +103,91 @@ impl Client {
     }
 }
 
-pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>) {
+/// Why the pool did not accept a subscribe or authorize request
+pub enum HandshakeError {
+    /// the access key was refused, reconnecting with it will not help
+    Unauthorized(String),
+    /// server side or protocol error, go through the normal reconnect path
+    Transient(String),
+}
+
+impl fmt::Display for HandshakeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandshakeError::Unauthorized(reason) => write!(f, "unauthorized: {}", reason),
+            HandshakeError::Transient(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+/// Check the result/error payload of a handshake response.
+/// A `false` result, or a request level JSON-RPC error, on authorize means the key was refused;
+/// internal and server errors (-32603, -32000..-32099) are always transient.
+fn check_response(
+    result: Option<ResponseParams>,
+    error: Option<json_rpc_types::Error<()>>,
+    authorize: bool,
+) -> Result<(), HandshakeError> {
+    if let Some(error) = error {
+        let reason = format!("error {}: {}", error.code.code(), error.message);
+        return match error.code {
+            ErrorCode::InternalError => Err(HandshakeError::Transient(reason)),
+            ErrorCode::ServerError(code) if (-32099..=-32000).contains(&code) => Err(HandshakeError::Transient(reason)),
+            _ if authorize => Err(HandshakeError::Unauthorized(reason)),
+            _ => Err(HandshakeError::Transient(reason)),
+        };
+    }
+    match result {
+        Some(ResponseParams::Bool(false)) if authorize => Err(HandshakeError::Unauthorized(String::from("result is false"))),
+        Some(ResponseParams::Bool(false)) => Err(HandshakeError::Transient(String::from("result is false"))),
+        Some(_) => Ok(()),
+        None => Err(HandshakeError::Transient(String::from("empty response"))),
+    }
+}
+
+pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>, state: Arc<ProverState>) {
 
     task::spawn(async move {
         let receiver = client.receiver();
         let mut id = 1;
+        let mut pending = PendingSubmits::new(client.submit_timeout, SUBMIT_MAX_ATTEMPTS);
+        let mut grace = DisconnectGrace::new(client.disconnect_grace);
+        let mut sessions = 0u64;
         loop {
-            info!("Connecting to server...");
+            state.metrics.set_connected(None);
+            //tasks keep running while reconnecting, until the pool is gone for too long
+            grace.lost();
+            if grace.expired() {
+                let tasks = state.scheduler.active().len();
+                if tasks > 0 {
+                    warn!("No pool session for {} seconds, cancel {} task(s)", client.disconnect_grace.as_secs(), tasks);
+                    state.cancel_tasks();
+                }
+            }
+            let endpoint = client.endpoints.lock().await.current().clone();
+            let server = endpoint.to_string();
+            info!("Connecting to server {}...", server);
 
-            match timeout(Duration::from_secs(10), TcpStream::connect(&client.server)).await {
+            match timeout(Duration::from_secs(10), connect(&client, &endpoint)).await {
                 Ok(socket) => match socket {
                     Ok(socket) => {
-                        info!("Connected to {}", client.server);
-                        let mut framed = Framed::new(socket, StratumCodec::default());
+                        info!("Connected to {}", server);
+                        let codec = if endpoint.binary {
+                            PoolCodec::Binary(BinaryCodec::new(client.max_frame_length.max(DEFAULT_MAX_BINARY_FRAME_LENGTH)))
+                        } else {
+                            PoolCodec::Stratum(StratumCodec::new(client.max_frame_length))
+                        };
+                        let mut framed = Framed::new(socket, codec);
 
                         //step1:send Subscribe msg
                         let handshake = StratumMessage::Subscribe(
                             Id::Num(id),
-                            "test".to_string(),
-                            "test".to_string(),
-                            2, //just for test
-                            4,
-                            6,
+                            host::user_agent(),
+                            PROTOCOL_VERSION.to_string(),
+                            client.hardware.cpu,
+                            client.hardware.gpu,
+                            client.hardware.memory_gb,
+                            CAPABILITIES.iter().map(|capability| capability.name().to_string()).collect(),
                         );
                         id += 1;
                         if let Err(e) = framed.send(handshake).await {
@@ -86,26 +196,36 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                             info!("Send handshake msg over");
                         }
 
-                        match framed.next().await {
+                        let session = match framed.next().await {
                             None => {
                                 error!("Unexpected end of stream");
-                                sleep(Duration::from_secs(2)).await;
+                                retry_later(&client).await;
                                 continue;
                             }
                             Some(Ok(message)) => match message {
-                                StratumMessage::Response(_, _, result) => {
-                                    info!("Handshake successful,result is {:?}",result);
+                                StratumMessage::Response(_, result, error) => {
+                                    let pool_capabilities = result.as_ref().and_then(|result| result.capabilities()).unwrap_or_default();
+                                    if let Err(e) = check_response(result, error, false) {
+                                        error!("Handshake failed: {}", e);
+                                        retry_later(&client).await;
+                                        continue;
+                                    }
+                                    let session = Session::negotiate(&pool_capabilities);
+                                    info!("Handshake successful, capabilities: {}", session);
+                                    session
                                 }
                                 _ => {
                                     error!("Unexpected message: {:?}", message.name());
+                                    retry_later(&client).await;
+                                    continue;
                                 }
                             },
                             Some(Err(e)) => {
                                 error!("Error receiving handshake: {}", e);
-                                sleep(Duration::from_secs(2)).await;
+                                retry_later(&client).await;
                                 continue;
                             }
-                        }
+                        };
 
                         //step2:send Authorize msg
                         let worker_access_key = client.name.clone();
@@ -121,20 +241,53 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                         match framed.next().await {
                             None => {
                                 error!("Unexpected end of stream");
-                                sleep(Duration::from_secs(2)).await;
+                                retry_later(&client).await;
                                 continue;
                             }
                             Some(Ok(message)) => match message {
-                                StratumMessage::Response(_, _, _) => {
-                                    info!("Authorization successful");
-                                }
+                                StratumMessage::Response(_, result, error) => match check_response(result, error, true) {
+                                    Ok(()) => {
+                                        let mut endpoints = client.endpoints.lock().await;
+                                        endpoints.on_connected();
+                                        if sessions > 0 {
+                                            state.metrics.reconnected();
+                                        }
+                                        sessions += 1;
+                                        state.metrics.set_connected(Some(&server));
+                                        info!("Authorization successful,pool {} priority {}", server, endpoints.priority());
+                                        if let Some(lost_for) = grace.restored() {
+                                            let tasks = state.scheduler.active().len();
+                                            if tasks > 0 {
+                                                info!("Session restored after {} seconds, {} task(s) kept running", lost_for.as_secs(), tasks);
+                                            }
+                                        }
+                                    }
+                                    Err(HandshakeError::Unauthorized(reason)) => {
+                                        error!("Authorization rejected by pool, check the access key: {}", reason);
+                                        match client.auth_backoff {
+                                            Some(backoff) => {
+                                                warn!("Retry authorization in {} seconds", backoff.as_secs());
+                                                sleep(backoff).await;
+                                                continue;
+                                            }
+                                            None => std::process::exit(AUTH_FAILED_EXIT_CODE),
+                                        }
+                                    }
+                                    Err(e) => {
+                                        error!("Authorization failed: {}", e);
+                                        retry_later(&client).await;
+                                        continue;
+                                    }
+                                },
                                 _ => {
                                     error!("Unexpected message: {:?}", message.name());
+                                    retry_later(&client).await;
+                                    continue;
                                 }
                             },
                             Some(Err(e)) => {
                                 error!("Error receiving authorization: {}", e);
-                                sleep(Duration::from_secs(2)).await;
+                                retry_later(&client).await;
                                 continue;
                             }
                         }
@@ -143,13 +296,29 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                         info!("send heartbeat to server when startup");
     
                  
-                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),String::from(""),String::from("")); //initial heartbeat
+                        let status = session.supports(Capability::Progress).then(|| heartbeat_status(&state, None, &HostLoad::sample(), 0));
+                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),String::from(""),String::from(""),status); //initial heartbeat
+                        let mut heartbeats = HashMap::new();
+                        heartbeats.insert(id, Instant::now());
+                        id += 1;
                         if let Err(e) = framed.send(heartbeat).await {
                                 error!("Error sending heartbeat in startup: {}", e);
                             } else {
                                 info!("Sent heartbeat msg over");
                         }
-    
+
+                        let resend = replay(&mut pending, &client.outbox);
+                        //binary frames carry the proof bytes whole
+                        let max_frame = (session.supports(Capability::ChunkedSubmit) && !endpoint.binary)
+                            .then_some(client.max_frame_length);
+                        let format = SubmitFormat {
+                            proof: if session.supports(Capability::ProofV1) { ProofFormat::V1 } else { ProofFormat::Legacy },
+                            invalid_proof: session.supports(Capability::InvalidProof),
+                        };
+                        for (record, attempts) in resend {
+                            info!("Resend the {} proof of task {}", record.project_name, record.task_id);
+                            send_submit(&mut framed, &mut pending, record, attempts + 1, &mut id, max_frame, format).await;
+                        }
 
                         let receiver = &mut *receiver.lock().await;
                         let mut heartbeat_interval = tokio::time::interval(Duration::from_secs(3));
@@ -157,32 +326,59 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                             tokio::select! {
                                 //process the msg send by prover
                                 Some(message) = receiver.recv() => { 
-                                    // let message = message.clone();
-                                    let name = message.name();
-                                    if let Err(e) = framed.send(message).await {
-                                        error!("Error sending {}: {:?}", name, e);
-                                        cancel_task().await;
+                                    //give every submit its own id to match the pool's response
+                                    match SubmitRecord::from_message(message) {
+                                        Ok(record) => send_submit(&mut framed, &mut pending, record, 1, &mut id, max_frame, format).await,
+                                        Err(message) => {
+                                            let name = message.name();
+                                            if let Err(e) = framed.send(message).await {
+                                                error!("Error sending {}: {:?}", name, e);
+                                            }
+                                        }
                                     }
                                 }
 
                                 _ = heartbeat_interval.tick() => {
-                                    let task = LATEST_TASK_CONTENT.lock().await;
-                                    let task_current = (*task).clone();
-                                    let heart_msg: Vec<&str> =task_current.split("#").collect();
-                                    if heart_msg.len()==2 {
-                                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),heart_msg[0].to_string(),heart_msg[1].to_string());  
+                                    for (record, attempts) in expired(&mut pending, &client.outbox) {
+                                        warn!("No response for the {} proof of task {},resend it", record.project_name, record.task_id);
+                                        send_submit(&mut framed, &mut pending, record, attempts + 1, &mut id, max_frame, format).await;
+                                    }
+
+                                    //forget heartbeats the pool never answered
+                                    heartbeats.retain(|_, sent: &mut Instant| sent.elapsed() < HEARTBEAT_RTT_WINDOW);
+
+                                    //one heartbeat per task in process, an empty one when idle
+                                    let tasks = state.scheduler.active();
+                                    let host = HostLoad::sample();
+                                    let active_slots = tasks.iter().filter(|task| task.phase != TaskPhase::Queued).count();
+                                    for task in &tasks {
+                                        let status = session.supports(Capability::Progress).then(|| heartbeat_status(&state, Some(task), &host, active_slots));
+                                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),task.project_name.clone(),task.task_id.to_string(),status);  
+                                        heartbeats.insert(id, Instant::now());
+                                        id += 1;
                                         if let Err(e) = framed.send(heartbeat).await {
                                             error!("Error sending heartbeat in loop: {}", e);
-                                            cancel_task().await;
                                         } else {
-                                            info!("Loop Sent {} heartbeat msg over block :{}",heart_msg[0].to_string(),heart_msg[1].to_string());
+                                            debug!("Loop Sent {} heartbeat msg to pool {} over block :{} ({})",task.project_name,server,task.task_id,task.phase);
                                         }
-                                    }else {
-                                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),String::from(""),String::from("")); //initial heartbeat
+                                    }
+                                    if tasks.is_empty() {
+                                        let status = session.supports(Capability::Progress).then(|| heartbeat_status(&state, None, &host, 0));
+                                        let heartbeat = StratumMessage::Heartbeat(Id::Num(id),String::from(""),String::from(""),status);
+                                        heartbeats.insert(id, Instant::now());
+                                        id += 1;
                                         if let Err(e) = framed.send(heartbeat).await {
                                                 error!("Error sending heartbeat in startup: {}", e);
                                         } else {
-                                                info!("Sent heartbeat msg over no any task");
+                                                debug!("Sent heartbeat msg to pool {} over no any task", server);
+                                        }
+
+                                        //only leave a backup pool while idle
+                                        let mut endpoints = client.endpoints.lock().await;
+                                        if pending.is_empty() && endpoints.should_fall_back() {
+                                            endpoints.fall_back();
+                                            info!("Moving from pool {} back to the preferred pool {}", server, endpoints.current());
+                                            break;
                                         }
                                     }
                                 }
@@ -194,10 +390,8 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                                         match message {
                                             StratumMessage::Notify(id, project_name,task_id,task_content,_) => { 
                                                 info!("zkpool : receive {} task of {}",project_name.clone(),task_id);
-                                                let resp = StratumMessage::Response(id,Some(ResponseParams::Bool(true)),Some(json_rpc_types::Error::from_code(json_rpc_types::ErrorCode::ServerError(1)))); 
-                                                if let Err(e) = framed.send(resp).await {
+                                                if let Err(e) = framed.send(notify_ack(id)).await {
                                                     error!("Error send  notify Response: {}", e);
-                                                    cancel_task().await;
                                                 } else {
                                                     debug!("Send notify Response Msg Over");
                                                 }
@@ -205,12 +399,27 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                                                 //parse parameter
                                                 if let Err(e) = prover_sender.send(ProverEvent::NewWork(project_name.clone(),task_id,task_content)).await {
                                                     error!("Error sending work to prover: {}", e);
-                                                    cancel_task().await;
                                                 } else {
                                                     debug!("Sent work to prover");
                                                 }
 
                                             }
+                                            StratumMessage::Response(Id::Num(response_id), result, error) => {
+                                                if let Some(record) = acknowledge(&mut pending, &client.outbox, response_id) {
+                                                    match submit_result(result, error) {
+                                                        Ok(()) => {
+                                                            state.metrics.submit_accepted();
+                                                            info!("zkpool accepted the {} proof of task {}", record.project_name, record.task_id);
+                                                        }
+                                                        Err(reason) => {
+                                                            state.metrics.submit_rejected();
+                                                            warn!("zkpool rejected the {} proof of task {}: {}", record.project_name, record.task_id, reason);
+                                                        }
+                                                    }
+                                                } else if let Some(sent) = heartbeats.remove(&response_id) {
+                                                    state.metrics.heartbeat_rtt(sent.elapsed());
+                                                }
+                                            }
                                             _ => {
                                                 debug!("ignore msg!!!");
                                             }
@@ -222,12 +431,8 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                                     }
                                     None => {
                                         error!("Disconnected from server");
-                                         //Clear the block task cache
-                                        let block_current = LATEST_TASK_CONTENT.clone();
-                                        let mut block_id_now = block_current.lock().await;
-                                        *block_id_now = String::from("");
-                                        cancel_task().await;
-                                        sleep(Duration::from_secs(1)).await;
+                                        let delay = client.endpoints.lock().await.on_disconnected();
+                                        sleep(delay).await;
                                         break;
                                     }
                                 }
@@ -235,32 +440,306 @@ pub async fn start(prover_sender: Arc<Sender<ProverEvent>>, client: Arc<Client>)
                         }
                     }
                     Err(e) => {
-                        error!("Failed to connect to operator: {}", e);
-                        cancel_task().await;
-                        sleep(Duration::from_secs(2)).await;
+                        error!("Failed to connect to operator {}: {}", server, e);
+                        retry_later(&client).await;
                     }
                 },
                 Err(_) => {
-                    error!("Failed to connect to operator: Timed out");
-                    cancel_task().await;
-                    sleep(Duration::from_secs(2)).await;
+                    error!("Failed to connect to operator {}: Timed out", server);
+                    retry_later(&client).await;
                 }
             }
         }
     });
 }
 
+/// Acknowledge a `zkpool.notify` with a `true` result.
+fn notify_ack(id: Id) -> StratumMessage {
+    StratumMessage::Response(id, Some(ResponseParams::Bool(true)), None)
+}
+
+/// Whether the pool accepted a submit, with its reason if not.
+fn submit_result(result: Option<ResponseParams>, error: Option<json_rpc_types::Error<()>>) -> Result<(), String> {
+    if let Some(error) = error {
+        return Err(format!("error {}: {}", error.code.code(), error.message));
+    }
+    match result {
+        Some(ResponseParams::Bool(false)) => Err(String::from("result is false")),
+        _ => Ok(()),
+    }
+}
 
-pub async fn cancel_task(){
-    let task_temp = TASK_HANDLER.clone();
-    let mut queue = task_temp.lock().await;
-    while queue.len() > 0 {
-        info!("clear the old task handle");
-        for i in queue.iter() {
-            i.abort();
-            drop(i)
+/// Submits to send on a new connection: those not acknowledged on the previous one,
+/// then the ones left in the outbox by an earlier run.
+fn replay(pending: &mut PendingSubmits, outbox: &Outbox) -> Vec<(SubmitRecord, u32)> {
+    let mut resend = pending.drain();
+    give_up(pending, outbox);
+    for record in outbox.load() {
+        if !resend.iter().any(|(pending, _)| pending.same_task(&record)) {
+            resend.push((record, 0));
         }
-        queue.clear(); 
     }
+    resend
+}
+
+/// The pool answered request `id`, a submit it acknowledges is deleted from the outbox.
+fn acknowledge(pending: &mut PendingSubmits, outbox: &Outbox, id: u64) -> Option<SubmitRecord> {
+    let record = pending.ack(id)?;
+    outbox.remove(&record);
+    Some(record)
 }
 
+/// Submits without a response in time, to be sent again.
+fn expired(pending: &mut PendingSubmits, outbox: &Outbox) -> Vec<(SubmitRecord, u32)> {
+    let resend = pending.expired();
+    give_up(pending, outbox);
+    resend
+}
+
+/// Submits given up are deleted from the outbox too, or every reconnect would send them again.
+fn give_up(pending: &mut PendingSubmits, outbox: &Outbox) {
+    for record in pending.given_up() {
+        outbox.remove(&record);
+    }
+}
+
+/// Send a submit with new request ids in `format`, in chunks if it is longer than `max_frame` bytes,
+/// and wait for the response to its last request. `attempts` counts this send.
+async fn send_submit(
+    framed: &mut Framed<Box<dyn PoolStream>, PoolCodec>,
+    pending: &mut PendingSubmits,
+    record: SubmitRecord,
+    attempts: u32,
+    id: &mut u64,
+    max_frame: Option<usize>,
+    format: SubmitFormat,
+) {
+    let message = record.to_message_as(*id, format);
+    let messages = match max_frame {
+        Some(max_frame) => match chunk::split_submit(message, max_frame) {
+            Ok(r) => r,
+            Err(e) => {
+                //it won't fit any better next time
+                error!("Unable to send the {} proof of task {} in chunks: {}", record.project_name, record.task_id, e);
+                pending.give_up(record);
+                return;
+            }
+        },
+        None => vec![message],
+    };
+    if messages.len() > 1 {
+        debug!("Send the {} proof of task {} in {} chunks", record.project_name, record.task_id, messages.len());
+    }
+    *id += messages.len() as u64;
+    pending.sent(*id - 1, record, attempts);
+    for message in messages {
+        let name = message.name();
+        if let Err(e) = framed.send(message).await {
+            error!("Error sending {}: {:?}", name, e);
+            return;
+        }
+    }
+}
+
+/// Protocol features agreed with the pool for one connection.
+#[derive(Debug, Default)]
+struct Session {
+    capabilities: BTreeSet<Capability>,
+}
+
+impl Session {
+    /// Keep the capabilities both sides support, a pool listing none gets the plain protocol.
+    fn negotiate(pool_capabilities: &[String]) -> Self {
+        let capabilities = pool_capabilities
+            .iter()
+            .filter_map(|name| Capability::from_name(name))
+            .filter(|capability| CAPABILITIES.contains(capability))
+            .collect();
+        Self { capabilities }
+    }
+
+    fn supports(&self, capability: Capability) -> bool {
+        self.capabilities.contains(&capability)
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.capabilities.is_empty() {
+            return write!(f, "none");
+        }
+        let names: Vec<&str> = self.capabilities.iter().map(|capability| capability.name()).collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+/// What a heartbeat reports about `task`, or about an idle prover if None.
+/// The remaining time is estimated from the average proof duration of the project.
+fn heartbeat_status(state: &ProverState, task: Option<&TaskState>, host: &HostLoad, active_slots: usize) -> HeartbeatStatus {
+    let (phase, elapsed, remaining) = match task {
+        Some(task) => {
+            let elapsed = task.since.elapsed();
+            let remaining = match task.phase {
+                TaskPhase::Queued => None,
+                TaskPhase::Proving => state
+                    .metrics
+                    .mean_proof_duration(&task.project_name)
+                    .map(|mean| mean.saturating_sub(elapsed).as_secs()),
+                TaskPhase::Verifying | TaskPhase::Submitting => Some(0),
+            };
+            (task.phase.to_string(), elapsed.as_secs(), remaining)
+        }
+        None => (String::from("idle"), 0, None),
+    };
+    HeartbeatStatus {
+        phase,
+        elapsed_secs: elapsed,
+        remaining_secs: remaining,
+        cpu_load: host.cpu_load,
+        free_memory_mb: host.free_memory_mb,
+        active_slots: active_slots as u64,
+    }
+}
+
+/// Open the transport to a pool endpoint, running the TLS handshake for `tls://` endpoints.
+async fn connect(client: &Client, endpoint: &PoolEndpoint) -> io::Result<Box<dyn PoolStream>> {
+    let socket = TcpStream::connect(&endpoint.address).await?;
+    if !endpoint.tls {
+        return Ok(Box::new(socket));
+    }
+    let connector = client
+        .tls
+        .as_ref()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "tls is not configured"))?;
+    Ok(Box::new(tls::connect(connector, &endpoint.address, socket).await?))
+}
+
+/// How long in-flight tasks survive without a pool session.
+struct DisconnectGrace {
+    window: Duration,
+    since: Option<Instant>,
+}
+
+impl DisconnectGrace {
+    fn new(window: Duration) -> Self {
+        Self { window, since: None }
+    }
+
+    /// No session, the window starts at the first call.
+    fn lost(&mut self) {
+        self.since.get_or_insert_with(Instant::now);
+    }
+
+    /// A session is established again, returns how long it was lost.
+    fn restored(&mut self) -> Option<Duration> {
+        self.since.take().map(|since| since.elapsed())
+    }
+
+    /// Whether the window is over, the next `lost` starts a new one.
+    fn expired(&mut self) -> bool {
+        match self.since {
+            Some(since) if since.elapsed() >= self.window => {
+                self.since = None;
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Wait for the backoff of the current endpoint, failing over to the next one when it keeps failing.
+async fn retry_later(client: &Client) {
+    let (delay, server) = {
+        let mut endpoints = client.endpoints.lock().await;
+        let delay = endpoints.on_failure();
+        (delay, endpoints.current().to_string())
+    };
+    info!("Reconnect to {} in {} ms", server, delay.as_millis());
+    sleep(delay).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::Encoder;
+
+    use super::*;
+
+    #[test]
+    fn ack_notify_with_result() {
+        let mut dst = BytesMut::new();
+        StratumCodec::default().encode(notify_ack(Id::Num(5)), &mut dst).unwrap();
+        assert_eq!(&dst[..], &b"{\"jsonrpc\":\"2.0\",\"result\":true,\"id\":5}\n"[..]);
+    }
+
+    #[test]
+    fn acked_submit_not_replayed() {
+        let state_dir = std::env::temp_dir().join(format!("zkpool-replay-{}", std::process::id()));
+        let outbox = Outbox::open(&state_dir, Duration::from_secs(60)).unwrap();
+        let mut pending = PendingSubmits::new(Duration::ZERO, 5);
+        for task_id in ["1", "2"] {
+            let record = SubmitRecord {
+                project_name: "taikoA5".to_string(),
+                task_id: task_id.to_string(),
+                proof: "#1#0x00".to_string(),
+                degree: 20,
+                time: 60,
+                status: 1,
+                reason: None,
+            };
+            outbox.store(&record).unwrap();
+            pending.sent(task_id.parse().unwrap(), record, 1);
+        }
+        //task 1 is resent before the pool answers its first send
+        let expired = pending.expired();
+        for (id, (record, attempts)) in (3..).zip(expired) {
+            pending.sent(id, record, attempts + 1);
+        }
+
+        assert_eq!(acknowledge(&mut pending, &outbox, 1).unwrap().task_id, "1");
+        let resend: Vec<String> = replay(&mut pending, &outbox).into_iter().map(|(record, _)| record.task_id).collect();
+        assert_eq!(resend, vec!["2"]);
+        //after a restart only the outbox is left
+        assert_eq!(outbox.load().len(), 1);
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[test]
+    fn given_up_submit_not_replayed() {
+        let state_dir = std::env::temp_dir().join(format!("zkpool-give-up-{}", std::process::id()));
+        let outbox = Outbox::open(&state_dir, Duration::from_secs(60)).unwrap();
+        let mut pending = PendingSubmits::new(Duration::ZERO, 2);
+        let record = SubmitRecord {
+            project_name: "taikoA5".to_string(),
+            task_id: "1".to_string(),
+            proof: "#1#0x00".to_string(),
+            degree: 20,
+            time: 60,
+            status: 1,
+            reason: None,
+        };
+        outbox.store(&record).unwrap();
+        pending.sent(1, record, 1);
+        let (record, attempts) = expired(&mut pending, &outbox).pop().unwrap();
+        pending.sent(2, record, attempts + 1);
+
+        //the second send is the last one, neither this connection nor the next sends it again
+        assert!(expired(&mut pending, &outbox).is_empty());
+        assert!(replay(&mut pending, &outbox).is_empty());
+        assert!(outbox.load().is_empty());
+        std::fs::remove_dir_all(state_dir).unwrap();
+    }
+
+    #[test]
+    fn negotiate_capabilities() {
+        let session = Session::negotiate(&[String::from("progress"), String::from("cancel"), String::from("future")]);
+        assert!(session.supports(Capability::Progress));
+        //not implemented by this prover
+        assert!(!session.supports(Capability::Cancel));
+        assert_eq!(session.to_string(), "progress");
+
+        let legacy = Session::negotiate(&[]);
+        assert!(!legacy.supports(Capability::Progress));
+        assert_eq!(legacy.to_string(), "none");
+    }
+}
@@ -0,0 +1,246 @@
+use std::{
+    collections::HashSet,
+    env, fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use url::Url;
+
+use crate::{backend, host::Hardware, prover::ProjectInfo, scheduler::TaskCost};
+
+/// Projects served when no `--config` is given.
+const BUILTIN_CONFIG: &str = include_str!("../app.yml");
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProverConfig {
+    pub projects: Vec<ProjectConfig>,
+    #[serde(default)]
+    pub hardware: HardwareConfig,
+}
+
+/// Hardware reported to the pool in place of the detected one, e.g. the limits of a container.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct HardwareConfig {
+    #[serde(default)]
+    pub cpu: Option<u64>,
+    #[serde(default)]
+    pub gpu: Option<u64>,
+    #[serde(default)]
+    pub memory_gb: Option<u64>,
+}
+
+impl HardwareConfig {
+    pub fn apply(&self, detected: Hardware) -> Hardware {
+        Hardware {
+            cpu: self.cpu.unwrap_or(detected.cpu),
+            gpu: self.gpu.unwrap_or(detected.gpu),
+            memory_gb: self.memory_gb.unwrap_or(detected.memory_gb),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ProjectConfig {
+    /// Project name as sent by the pool
+    pub name: String,
+    pub rpc_url: String,
+    /// Proof backend serving the project, the project name if not set
+    #[serde(default)]
+    pub backend: Option<String>,
+    /// Cpus and GiB of memory one task holds while proving, the backend defaults if not set
+    #[serde(default)]
+    pub cpu: Option<u32>,
+    #[serde(default)]
+    pub memory_gb: Option<u32>,
+    /// Verify proofs before submitting them
+    #[serde(default)]
+    pub verify: bool,
+    /// Hex file with the deployment code of the project's verifier contract, required by `verify`
+    #[serde(default)]
+    pub verifier: Option<PathBuf>,
+}
+
+impl ProverConfig {
+    /// Read the config file, or the built-in one if `path` is not set, then apply the environment overrides.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        let mut config = match path {
+            Some(path) => {
+                let text = fs::read_to_string(path).with_context(|| format!("read config {}", path.display()))?;
+                Self::parse(&text).with_context(|| format!("invalid config {}", path.display()))?
+            }
+            None => Self::parse(BUILTIN_CONFIG).context("invalid built-in config")?,
+        };
+        config.apply_env(|key| env::var(key).ok());
+        Ok(config)
+    }
+
+    fn parse(text: &str) -> anyhow::Result<Self> {
+        Ok(serde_yaml::from_str(text)?)
+    }
+
+    /// `ZKPOOL_RPC_URL_<PROJECT>` replaces the rpc url of a project, e.g. to keep an api key out of the file.
+    fn apply_env(&mut self, lookup: impl Fn(&str) -> Option<String>) {
+        for project in &mut self.projects {
+            if let Some(rpc_url) = lookup(&rpc_url_env(&project.name)) {
+                project.rpc_url = rpc_url;
+            }
+        }
+    }
+
+    /// Check every project and resolve its backend, reporting all problems at once.
+    pub fn projects(&self) -> anyhow::Result<Vec<ProjectInfo>> {
+        let mut errors = Vec::new();
+        let mut names = HashSet::new();
+        let mut projects = Vec::new();
+        if self.projects.is_empty() {
+            bail!("no project configured");
+        }
+        for (i, project) in self.projects.iter().enumerate() {
+            let at = format!("projects[{}] ({})", i, project.name);
+            if project.name.trim().is_empty() {
+                errors.push(format!("projects[{}]: name is empty", i));
+                continue;
+            }
+            if !names.insert(project.name.as_str()) {
+                errors.push(format!("{}: duplicate project name", at));
+            }
+            match Url::parse(&project.rpc_url) {
+                Ok(url) if url.scheme() == "http" || url.scheme() == "https" => {}
+                Ok(url) => errors.push(format!("{}: unsupported rpc_url scheme {}", at, url.scheme())),
+                //the url may hold an api key, it is not echoed
+                Err(e) => errors.push(format!("{}: invalid rpc_url: {}", at, e)),
+            }
+            let verifier = match &project.verifier {
+                Some(path) => match read_verifier(path) {
+                    Ok(code) => Some(Arc::new(code)),
+                    Err(e) => {
+                        errors.push(format!("{}: verifier {}: {:#}", at, path.display(), e));
+                        None
+                    }
+                },
+                None if project.verify => {
+                    errors.push(format!("{}: verify needs the verifier contract, set verifier", at));
+                    None
+                }
+                None => None,
+            };
+            let backend_name = project.backend.as_deref().unwrap_or(&project.name);
+            match backend::lookup(backend_name) {
+                Some(backend) => {
+                    let default_cost = backend.default_cost();
+                    let cost = TaskCost {
+                        cpu: project.cpu.unwrap_or(default_cost.cpu),
+                        memory_gb: project.memory_gb.unwrap_or(default_cost.memory_gb),
+                    };
+                    if cost.cpu == 0 {
+                        errors.push(format!("{}: cpu must be positive", at));
+                    }
+                    projects.push(ProjectInfo {
+                        name: project.name.clone(),
+                        rpc_url: project.rpc_url.clone(),
+                        backend,
+                        cost,
+                        verify: project.verify,
+                        verifier,
+                    })
+                }
+                None => errors.push(format!(
+                    "{}: unknown proof backend {}, available: {}",
+                    at,
+                    backend_name,
+                    backend::names().join(", ")
+                )),
+            }
+        }
+        if !errors.is_empty() {
+            bail!(errors.join("\n"));
+        }
+        Ok(projects)
+    }
+}
+
+fn read_verifier(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let text = fs::read_to_string(path)?;
+    let text = text.trim();
+    let code = hex::decode(text.strip_prefix("0x").unwrap_or(text)).context("not hex")?;
+    if code.is_empty() {
+        bail!("empty");
+    }
+    Ok(code)
+}
+
+fn rpc_url_env(project_name: &str) -> String {
+    let suffix: String = project_name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_uppercase() } else { '_' })
+        .collect();
+    format!("ZKPOOL_RPC_URL_{}", suffix)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builtin_config_is_valid() {
+        let config = ProverConfig::parse(BUILTIN_CONFIG).unwrap();
+        assert!(!config.projects().unwrap().is_empty());
+    }
+
+    #[test]
+    fn report_every_invalid_project() {
+        let mut config = ProverConfig::parse(
+            "projects:
+  - name: taikoA5
+    rpc_url: https://rpc.example.com
+  - name: taikoA5
+    rpc_url: ftp://rpc.example.com
+    verifier: /nonexistent/verifier.hex
+  - name: other
+    rpc_url: not a url/secret-key
+    verify: true
+",
+        )
+        .unwrap();
+        let error = config.projects().err().unwrap().to_string();
+        assert!(error.contains("projects[1] (taikoA5): duplicate project name"));
+        assert!(error.contains("projects[1] (taikoA5): unsupported rpc_url scheme ftp"));
+        assert!(error.contains("projects[2] (other): invalid rpc_url"));
+        assert!(!error.contains("secret-key"));
+        assert!(error.contains("projects[2] (other): unknown proof backend other"));
+        assert!(error.contains("projects[1] (taikoA5): verifier /nonexistent/verifier.hex"));
+        assert!(error.contains("projects[2] (other): verify needs the verifier contract"));
+
+        config.projects.truncate(1);
+        config.apply_env(|key| (key == "ZKPOOL_RPC_URL_TAIKOA5").then(|| "http://127.0.0.1:8545".to_string()));
+        assert_eq!(config.projects().unwrap()[0].rpc_url, "http://127.0.0.1:8545");
+    }
+
+    #[test]
+    fn override_hardware() {
+        let config = ProverConfig::parse(
+            "projects:
+  - name: taikoA5
+    rpc_url: https://rpc.example.com
+hardware:
+  cpu: 16
+  memory_gb: 64
+",
+        )
+        .unwrap();
+        let detected = Hardware { cpu: 128, gpu: 1, memory_gb: 512 };
+        assert_eq!(config.hardware.apply(detected), Hardware { cpu: 16, gpu: 1, memory_gb: 64 });
+    }
+
+    #[test]
+    fn reject_unknown_fields() {
+        let error = ProverConfig::parse("projects:\n  - name: taikoA5\n    rpc: https://rpc.example.com\n").unwrap_err();
+        assert!(error.to_string().contains("unknown field `rpc`"));
+    }
+}
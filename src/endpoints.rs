@@ -0,0 +1,182 @@
+use std::{
+    fmt,
+    str::FromStr,
+    time::{Duration, Instant},
+};
+
+use rand::Rng;
+
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+const BACKOFF_MAX: Duration = Duration::from_secs(120);
+
+/// One pool address, `tls://host:port` opts in to TLS, `bin://` and `bin+tls://` to the binary framing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PoolEndpoint {
+    pub address: String,
+    pub tls: bool,
+    /// length-prefixed binary messages instead of JSON lines
+    pub binary: bool,
+}
+
+impl FromStr for PoolEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, tls, binary) = match s.split_once("://") {
+            None => (s, false, false),
+            Some(("tcp", address)) => (address, false, false),
+            Some(("tls", address)) => (address, true, false),
+            Some(("bin", address)) => (address, false, true),
+            Some(("bin+tls", address)) => (address, true, true),
+            Some((scheme, _)) => return Err(format!("unsupported pool scheme {}", scheme)),
+        };
+        if address.is_empty() {
+            return Err(String::from("empty pool address"));
+        }
+        Ok(Self { address: address.to_string(), tls, binary })
+    }
+}
+
+impl fmt::Display for PoolEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.binary, self.tls) {
+            (false, false) => write!(f, "{}", self.address),
+            (false, true) => write!(f, "tls://{}", self.address),
+            (true, false) => write!(f, "bin://{}", self.address),
+            (true, true) => write!(f, "bin+tls://{}", self.address),
+        }
+    }
+}
+
+/// Pool endpoints in order of preference, with the reconnect policy between them.
+pub struct PoolEndpoints {
+    endpoints: Vec<PoolEndpoint>,
+    current: usize,
+    //consecutive failures on the current endpoint
+    failures: u32,
+    //consecutive failures since the last good session, drives the backoff
+    attempts: u32,
+    failover_after: u32,
+    fallback_interval: Duration,
+    connected_since: Option<Instant>,
+    //endpoint to return to if trying the preferred one again fails
+    probing_from: Option<usize>,
+}
+
+impl PoolEndpoints {
+    pub fn new(endpoints: Vec<PoolEndpoint>, failover_after: u32, fallback_interval: Duration) -> Self {
+        assert!(!endpoints.is_empty(), "at least one pool endpoint is required");
+        Self {
+            endpoints,
+            current: 0,
+            failures: 0,
+            attempts: 0,
+            failover_after: failover_after.max(1),
+            fallback_interval,
+            connected_since: None,
+            probing_from: None,
+        }
+    }
+
+    pub fn current(&self) -> &PoolEndpoint {
+        &self.endpoints[self.current]
+    }
+
+    /// Priority of the current endpoint, 0 is the preferred one.
+    pub fn priority(&self) -> usize {
+        self.current
+    }
+
+    /// The session on the current endpoint is established.
+    pub fn on_connected(&mut self) {
+        self.failures = 0;
+        self.attempts = 0;
+        self.probing_from = None;
+        self.connected_since = Some(Instant::now());
+    }
+
+    /// The session ended after it was established, reconnect quickly.
+    pub fn on_disconnected(&mut self) -> Duration {
+        self.connected_since = None;
+        jitter(BACKOFF_BASE)
+    }
+
+    /// Connecting or the handshake failed, returns how long to wait before the next attempt.
+    pub fn on_failure(&mut self) -> Duration {
+        self.connected_since = None;
+        if let Some(previous) = self.probing_from.take() {
+            //the preferred endpoint is still down, go back to the one that worked
+            self.current = previous;
+            self.failures = 0;
+            return jitter(BACKOFF_BASE);
+        }
+        self.failures += 1;
+        self.attempts += 1;
+        if self.failures >= self.failover_after && self.endpoints.len() > 1 {
+            self.current = (self.current + 1) % self.endpoints.len();
+            self.failures = 0;
+        }
+        let exp = BACKOFF_BASE.saturating_mul(1 << (self.attempts - 1).min(16));
+        jitter(exp.min(BACKOFF_MAX))
+    }
+
+    /// Whether it is time to try the preferred endpoint again.
+    pub fn should_fall_back(&self) -> bool {
+        self.current != 0
+            && self
+                .connected_since
+                .map_or(false, |since| since.elapsed() >= self.fallback_interval)
+    }
+
+    /// Switch to the preferred endpoint, remembering the current one in case it is still down.
+    pub fn fall_back(&mut self) {
+        self.probing_from = Some(self.current);
+        self.current = 0;
+        self.failures = 0;
+        self.connected_since = None;
+    }
+}
+
+/// Random delay in [delay/2, delay] so that provers do not reconnect in lockstep.
+fn jitter(delay: Duration) -> Duration {
+    let millis = delay.as_millis() as u64;
+    Duration::from_millis(rand::thread_rng().gen_range(millis / 2..=millis))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_schemes() {
+        for address in ["a:1", "tls://a:1", "bin://a:1", "bin+tls://a:1"] {
+            assert_eq!(address.parse::<PoolEndpoint>().unwrap().to_string(), address);
+        }
+        let endpoint: PoolEndpoint = "bin+tls://a:1".parse().unwrap();
+        assert!(endpoint.tls && endpoint.binary);
+        assert!("udp://a:1".parse::<PoolEndpoint>().is_err());
+    }
+
+    #[test]
+    fn failover_and_fall_back() {
+        let mut endpoints = PoolEndpoints::new(
+            vec!["a:1".parse().unwrap(), "tls://b:1".parse().unwrap()],
+            2,
+            Duration::from_secs(0),
+        );
+        let first = endpoints.on_failure();
+        assert!(first <= BACKOFF_BASE);
+        assert_eq!(endpoints.current().to_string(), "a:1");
+        let second = endpoints.on_failure();
+        assert!(second >= BACKOFF_BASE && second <= BACKOFF_BASE * 2);
+        assert_eq!(endpoints.current().to_string(), "tls://b:1");
+
+        endpoints.on_connected();
+        assert!(endpoints.should_fall_back());
+        endpoints.fall_back();
+        assert_eq!(endpoints.current().to_string(), "a:1");
+        //a single failed probe returns to the working endpoint
+        endpoints.on_failure();
+        assert_eq!(endpoints.current().to_string(), "tls://b:1");
+    }
+}
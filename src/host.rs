@@ -0,0 +1,87 @@
+use std::fs;
+
+/// User agent sent in `zkpool.subscribe`, e.g. `zkpool-prover/0.0.1 (linux; x86_64)`.
+pub fn user_agent() -> String {
+    format!("zkpool-prover/{} ({}; {})", env!("CARGO_PKG_VERSION"), std::env::consts::OS, std::env::consts::ARCH)
+}
+
+/// Hardware reported to the pool when subscribing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hardware {
+    pub cpu: u64,
+    pub gpu: u64,
+    pub memory_gb: u64,
+}
+
+impl Hardware {
+    /// Detection sees the host, not the limits of a container, which the config can override.
+    pub fn detect() -> Self {
+        Self {
+            cpu: num_cpus::get() as u64,
+            gpu: count_gpus(),
+            memory_gb: fs::read_to_string("/proc/meminfo")
+                .ok()
+                .and_then(|s| parse_meminfo(&s, "MemTotal"))
+                .map_or(0, |kb| kb / (1024 * 1024)),
+        }
+    }
+}
+
+/// Nvidia devices, /dev/nvidia0, /dev/nvidia1, ...
+fn count_gpus() -> u64 {
+    let entries = match fs::read_dir("/dev") {
+        Ok(r) => r,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            name.strip_prefix("nvidia").map_or(false, |index| !index.is_empty() && index.chars().all(|c| c.is_ascii_digit()))
+        })
+        .count() as u64
+}
+
+/// Load of this host when a heartbeat is sent, read from /proc and None where that is not available.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostLoad {
+    /// 1 minute load average
+    pub cpu_load: Option<f64>,
+    /// memory available to new processes, in MiB
+    pub free_memory_mb: Option<u64>,
+}
+
+impl HostLoad {
+    pub fn sample() -> Self {
+        Self {
+            cpu_load: fs::read_to_string("/proc/loadavg").ok().and_then(|s| parse_loadavg(&s)),
+            free_memory_mb: fs::read_to_string("/proc/meminfo").ok().and_then(|s| parse_meminfo(&s, "MemAvailable")).map(|kb| kb / 1024),
+        }
+    }
+}
+
+fn parse_loadavg(loadavg: &str) -> Option<f64> {
+    loadavg.split_whitespace().next()?.parse().ok()
+}
+
+/// Value of `key` in kB.
+fn parse_meminfo(meminfo: &str, key: &str) -> Option<u64> {
+    meminfo.lines().find_map(|line| {
+        let value = line.strip_prefix(key)?.strip_prefix(':')?;
+        value.split_whitespace().next()?.parse().ok()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_files() {
+        assert_eq!(parse_loadavg("7.52 6.10 5.03 9/1234 56789\n"), Some(7.52));
+        let meminfo = "MemTotal:       65842332 kB\nMemFree:         1204400 kB\nMemAvailable:   20971520 kB\n";
+        assert_eq!(parse_meminfo(meminfo, "MemAvailable"), Some(20971520));
+        assert_eq!(parse_meminfo(meminfo, "Mem"), None);
+    }
+}
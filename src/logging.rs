@@ -0,0 +1,209 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use tracing::Subscriber;
+use tracing_subscriber::{
+    fmt::MakeWriter,
+    layer::SubscriberExt,
+    registry::LookupSpan,
+    reload,
+    util::SubscriberInitExt,
+    EnvFilter, Layer, Registry,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogRotate {
+    Never,
+    Daily,
+    Size,
+}
+
+/// Where and how to log, from the command line.
+pub struct LogSettings {
+    pub format: LogFormat,
+    /// `RUST_LOG` style directives, e.g. `info,zkpool_prover::client=debug`
+    pub filter: String,
+    pub file: Option<PathBuf>,
+    pub rotate: LogRotate,
+    pub max_size: u64,
+    /// rotated files kept next to the current one
+    pub keep: usize,
+    /// log to stderr instead of stdout
+    pub stderr: bool,
+}
+
+/// Changes the log filter of the running process.
+#[derive(Clone)]
+pub struct LogHandle(reload::Handle<EnvFilter, Registry>);
+
+impl LogHandle {
+    pub fn set_filter(&self, directives: &str) -> Result<(), String> {
+        let filter = EnvFilter::try_new(directives).map_err(|e| e.to_string())?;
+        self.0.reload(filter).map_err(|e| e.to_string())
+    }
+}
+
+/// Install the global subscriber: stdout or stderr, plus the log file if set.
+pub fn init(settings: &LogSettings) -> anyhow::Result<LogHandle> {
+    let filter = EnvFilter::try_new(&settings.filter)
+        .map_err(|e| anyhow::anyhow!("invalid log filter {}: {}", settings.filter, e))?;
+    let (filter, handle) = reload::Layer::new(filter);
+    let file = match &settings.file {
+        Some(path) => {
+            let rotation = match settings.rotate {
+                LogRotate::Never => Rotation::Never,
+                LogRotate::Daily => Rotation::Daily,
+                LogRotate::Size => Rotation::Size(settings.max_size),
+            };
+            let file = RotatingFile::open(path, rotation, settings.keep)
+                .map_err(|e| anyhow::anyhow!("open log file {}: {}", path.display(), e))?;
+            Some(fmt_layer(settings.format, Mutex::new(file), false))
+        }
+        None => None,
+    };
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(if settings.stderr {
+            fmt_layer(settings.format, io::stderr, true)
+        } else {
+            fmt_layer(settings.format, io::stdout, true)
+        })
+        .with(file)
+        .try_init()?;
+    Ok(LogHandle(handle))
+}
+
+fn fmt_layer<S, W>(format: LogFormat, writer: W, ansi: bool) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer);
+    match format {
+        LogFormat::Text => Box::new(layer.with_ansi(ansi)),
+        LogFormat::Json => Box::new(layer.json()),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    Daily,
+    /// rotate before the file grows past this many bytes
+    Size(u64),
+}
+
+/// Log file appended to across restarts, rotated to `<file>.1`, `<file>.2`, ... with the oldest deleted.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: Rotation,
+    keep: usize,
+    file: File,
+    size: u64,
+    day: u64,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: Rotation, keep: usize) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            keep,
+            file,
+            size,
+            day: today(),
+        })
+    }
+
+    fn rotated(&self, index: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_owned();
+        name.push(format!(".{}", index));
+        PathBuf::from(name)
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.keep == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let _ = fs::remove_file(self.rotated(self.keep));
+            for index in (1..self.keep).rev() {
+                let from = self.rotated(index);
+                if from.exists() {
+                    fs::rename(from, self.rotated(index + 1))?;
+                }
+            }
+            fs::rename(&self.path, self.rotated(1))?;
+        }
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        self.size = 0;
+        self.day = today();
+        Ok(())
+    }
+
+    fn should_rotate(&self, len: usize) -> bool {
+        match self.rotation {
+            Rotation::Never => false,
+            Rotation::Daily => today() != self.day,
+            Rotation::Size(max) => self.size > 0 && self.size + len as u64 > max,
+        }
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.should_rotate(buf.len()) {
+            //keep logging to the current file if rotating fails
+            if let Err(e) = self.rotate() {
+                eprintln!("failed to rotate log file {}: {}", self.path.display(), e);
+            }
+        }
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Days since the epoch, in UTC.
+fn today() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_by_size_with_retention() {
+        let dir = std::env::temp_dir().join(format!("zkpool-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("prover.log");
+        let mut file = RotatingFile::open(&path, Rotation::Size(10), 2).unwrap();
+        for line in ["aaaaaaaa\n", "bbbbbbbb\n", "cccccccc\n", "dddddddd\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "dddddddd\n");
+        assert_eq!(fs::read_to_string(dir.join("prover.log.1")).unwrap(), "cccccccc\n");
+        assert_eq!(fs::read_to_string(dir.join("prover.log.2")).unwrap(), "bbbbbbbb\n");
+        assert!(!dir.join("prover.log.3").exists());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
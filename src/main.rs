@@ -1,37 +1,53 @@
 extern crate core;
 
+mod backend;
 #[forbid(unsafe_code)]
 mod client;
+mod config;
+mod endpoints;
+mod host;
+mod logging;
+mod message;
+mod metrics;
+mod outbox;
+mod pool;
+mod prove;
 mod prover;
+mod reload;
+mod scheduler;
+mod submit;
+mod tls;
+
+use std::{
+    net::{SocketAddr, ToSocketAddrs},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
 
-use std::{net::ToSocketAddrs, sync::Arc};
-
-use clap::Parser;
-
-use tracing::{error, info};
-use tracing_subscriber::layer::SubscriberExt;
+use clap::{Parser, Subcommand};
+use taiko_stratum::codec::DEFAULT_MAX_FRAME_LENGTH;
 
-use crate::prover::ProjectInfo;
+use tracing::{error, info, warn};
 
 use machine_uid;
 
 use crate::{
     client::{start, Client},
+    config::ProverConfig,
+    endpoints::{PoolEndpoint, PoolEndpoints},
+    host::Hardware,
+    logging::{LogFormat, LogRotate, LogSettings},
+    outbox::Outbox,
+    pool::Pool,
+    backend::worker::WorkerOpt,
+    prove::ProveOpt,
+    scheduler::Capacity,
     prover::Prover,
+    tls::TlsSettings,
 };
 
-use crate::prover::PROJECT_LIST;
-
-extern crate serde_yaml;
-extern crate serde;
-
-use serde::{Serialize, Deserialize};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProverConfig {
-    name_list: Vec<String>,
-    rpc_url_list: Vec<String>,
-}
+use crate::prover::ProverState;
 
 #[derive(Debug, Parser)]
 #[clap(name = "prover", about = "Standalone prover.")]
@@ -45,66 +61,220 @@ struct Opt {
     version: bool,
 
     /// Prover access key (...)
-    #[clap(short = 'k', long = "access_key")]
+    #[clap(short = 'k', long = "access_key", env = "ZKPOOL_ACCESS_KEY", hide_env_values = true)]
     access: Option<String>,
 
      /// Prover device id (...)
      #[clap(short = 'u', long = "uuid")]
      unique_id: Option<String>,
 
-    /// Pool server address
-    #[clap(short = 'p', long = "pool")]
-    pool: Option<String>,
+    /// Project config file, the built-in projects are served if not set
+    #[clap(short = 'c', long = "config", env = "ZKPOOL_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Unix socket accepting control commands: `reload` re-reads the project config like SIGHUP,
+    /// `log <filter>` changes the log filter
+    #[clap(long = "control_socket")]
+    control_socket: Option<PathBuf>,
+
+    /// Number of tasks proven at once, as far as the cpus and memory allow
+    #[clap(long = "slots", default_value_t = 1)]
+    slots: usize,
+
+    /// GiB of memory available to proving, tasks are admitted only if their memory fits
+    #[clap(long = "max_memory")]
+    max_memory: Option<u32>,
 
-    /// Number of threads
+    /// Serve Prometheus metrics at http://<addr>/metrics, e.g. 127.0.0.1:9100
+    #[clap(long = "metrics_addr", alias = "metrics-addr")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Pool server address, tls://host:port for TLS, repeat or comma separate to add backup pools in order of preference
+    #[clap(short = 'p', long = "pool", value_delimiter = ',')]
+    pool: Vec<String>,
+
+    /// Switch to the next pool after this many consecutive connection failures
+    #[clap(long = "failover_after", default_value_t = 3)]
+    failover_after: u32,
+
+    /// Seconds connected to a backup pool before trying the preferred pool again
+    #[clap(long = "fallback_interval", default_value_t = 600)]
+    fallback_interval: u64,
+
+    /// Number of proving threads, all cpus if not set
     #[clap(short = 't', long = "threads")]
     threads: Option<u16>,
 
     /// Output log to file
     #[clap(short = 'o', long = "log")]
-    log: Option<String>,
+    log: Option<PathBuf>,
+
+    /// Log line format
+    #[clap(long = "log_format", alias = "log-format", value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// RUST_LOG style log filter, e.g. info,zkpool_prover::client=debug, RUST_LOG is used if not set
+    #[clap(long = "log_filter")]
+    log_filter: Option<String>,
+
+    /// When to rotate the log file
+    #[clap(long = "log_rotate", value_enum, default_value_t = LogRotate::Daily)]
+    log_rotate: LogRotate,
+
+    /// MiB after which the log file is rotated with --log_rotate size
+    #[clap(long = "log_max_size", default_value_t = 100)]
+    log_max_size: u64,
+
+    /// Number of rotated log files to keep
+    #[clap(long = "log_keep", default_value_t = 7)]
+    log_keep: usize,
+
+    /// PEM CA bundle for tls:// pools, the built-in web roots are used if not set
+    #[clap(long = "tls_ca")]
+    tls_ca: Option<PathBuf>,
+
+    /// Base64 sha256 of the pool public key (SPKI) to pin for tls:// pools, repeatable
+    #[clap(long = "tls_pin", value_delimiter = ',')]
+    tls_pin: Vec<String>,
+
+    /// PEM client certificate to authenticate the device to tls:// pools
+    #[clap(long = "tls_cert", requires = "tls_key")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM private key of the client certificate
+    #[clap(long = "tls_key", requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
+
+    /// Longest line in bytes read from or sent to the pool, at least 4096; longer proofs are submitted
+    /// in chunks if the pool supports chunked-submit
+    #[clap(long = "max_frame_length", default_value_t = DEFAULT_MAX_FRAME_LENGTH)]
+    max_frame_length: usize,
+
+    /// Seconds to wait for the pool to acknowledge a proof before sending it again
+    #[clap(long = "submit_timeout", default_value_t = 60)]
+    submit_timeout: u64,
+
+    /// Seconds proofs keep running without a pool session before they are cancelled
+    #[clap(long = "disconnect_grace", default_value_t = 300)]
+    disconnect_grace: u64,
+
+    /// Directory for state kept across restarts, such as proofs not yet acknowledged by the pool
+    #[clap(long = "state_dir", default_value = "zkpool-state")]
+    state_dir: PathBuf,
+
+    /// Seconds after which an unacknowledged proof is no longer replayed
+    #[clap(long = "outbox_max_age", default_value_t = 86400)]
+    outbox_max_age: u64,
+
+    /// Seconds to wait before retrying when the pool rejects the access key, exit if not set
+    #[clap(long = "auth_backoff")]
+    auth_backoff: Option<u64>,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Prove one task and write the proof as json, without connecting to a pool
+    Prove(ProveOpt),
+    /// Prove one task for a parent prover, see `backend::worker`
+    #[clap(hide = true)]
+    ProveWorker(WorkerOpt),
 }
 
 #[tokio::main]
 async fn main() {
-     let yaml_str = include_str!("../app.yml");
-     let prover_config: ProverConfig = serde_yaml::from_str(yaml_str)
-         .expect("app.yaml read failed!");
-    
-     for i in 0..prover_config.name_list.len(){
-        let one_project = ProjectInfo {
-            name:prover_config.name_list[i].clone(),
-            rpc_url:prover_config.rpc_url_list[i].clone(),
-         }; 
-
-         let pk_temp = PROJECT_LIST.clone();
-         let mut pk_map = pk_temp.lock().await;
-         pk_map.insert(prover_config.name_list[i].clone(), one_project);
-
-     }
-     
     let opt = Opt::parse();
 
-    let tracing_level = if opt.debug {
-        tracing::Level::DEBUG
-    } else {
-        tracing::Level::INFO
+    let log_filter = opt
+        .log_filter
+        .clone()
+        .or_else(|| std::env::var("RUST_LOG").ok())
+        .unwrap_or_else(|| String::from(if opt.debug { "debug" } else { "info" }));
+    let log_settings = LogSettings {
+        format: opt.log_format,
+        filter: log_filter,
+        file: opt.log.clone(),
+        rotate: opt.log_rotate,
+        max_size: opt.log_max_size.saturating_mul(1024 * 1024),
+        keep: opt.log_keep,
+        //stdout may carry the proof
+        stderr: opt.command.is_some(),
+    };
+    let log_handle = match logging::init(&log_settings) {
+        Ok(r) => r,
+        Err(e) => {
+            eprintln!("Unable to initialize logging: {:#}", e);
+            std::process::exit(1);
+        }
     };
 
-    let subscriber = tracing_subscriber::fmt::Subscriber::builder()
-        .with_max_level(tracing_level)
-        .finish();
+    let (projects, hardware) = match ProverConfig::load(opt.config.as_deref())
+        .and_then(|config| Ok((config.projects()?, config.hardware.apply(Hardware::detect()))))
+    {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Invalid project config: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
-    if let Some(log) = opt.log {
-        let file = std::fs::File::create(log).unwrap();
-        let file = tracing_subscriber::fmt::layer().with_writer(file).with_ansi(false);
-        tracing::subscriber::set_global_default(subscriber.with(file))
-            .expect("unable to set global default subscriber");
-    } else {
-        tracing::subscriber::set_global_default(subscriber).expect("unable to set global default subscriber");
-    }   
+    //proving gets its own threads, the tokio runtime only serves the pool connection
+    let threads = opt.threads.map_or_else(num_cpus::get, |threads| threads.max(1) as usize);
+    if let Err(e) = rayon::ThreadPoolBuilder::new().num_threads(threads).build_global() {
+        warn!("Unable to size the proving thread pool: {}", e);
+    }
+    if let Some(Command::ProveWorker(_)) = &opt.command {
+        if let Err(e) = backend::worker::serve().await {
+            error!("Prover worker failed: {:#}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(0);
+    }
+    let pool = Arc::new(Pool::new(threads, tokio::runtime::Handle::current()));
+
+    if let Some(Command::Prove(prove_opt)) = &opt.command {
+        let report = match prove::run(prove_opt, &projects, &pool).await {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Unable to prove the task: {:#}", e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = prove::write(prove_opt, &report) {
+            error!("Unable to write the proof: {:#}", e);
+            std::process::exit(1);
+        }
+        std::process::exit(if report.status == prover::PROOF_STATUS_OK { 0 } else { 1 });
+    }
+
+    for project in &projects {
+        info!("Serve project {}", project.name);
+    }
+    //the capacity is set once the proving threads are known
+    let state = Arc::new(ProverState::new(projects, Capacity::default()));
+
+    #[cfg(unix)]
+    {
+        if let Err(e) = reload::watch_sighup(state.clone(), opt.config.clone()) {
+            warn!("Unable to watch SIGHUP, project reload is disabled: {}", e);
+        }
+        if let Some(path) = &opt.control_socket {
+            if let Err(e) = reload::serve_control(state.clone(), opt.config.clone(), path.clone(), log_handle.clone()) {
+                error!("Unable to open control socket {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    if let Some(addr) = opt.metrics_addr {
+        if let Err(e) = metrics::serve(addr, state.clone()) {
+            error!("Unable to serve metrics on {}: {}", addr, e);
+            std::process::exit(1);
+        }
+    }
 
-    
     if opt.version {
         println!("0.1.1");
         std::process::exit(1);
@@ -117,7 +287,7 @@ async fn main() {
         }
     };
     
-    if opt.pool.is_none() {
+    if opt.pool.is_empty() {
         error!("Pool address is required!");
         std::process::exit(1);
     }
@@ -127,18 +297,62 @@ async fn main() {
     }
 
     let access_key = opt.access.unwrap();
-    let pool = opt.pool.unwrap();
 
-    if let Err(e) = pool.to_socket_addrs() {
-        error!("Invalid pool address {}: {}", pool, e);
-        std::process::exit(1);
+    let mut pool_endpoints = Vec::new();
+    for address in &opt.pool {
+        let endpoint = match address.parse::<PoolEndpoint>() {
+            Ok(r) => r,
+            Err(e) => {
+                error!("Invalid pool address {}: {}", address, e);
+                std::process::exit(1);
+            }
+        };
+        if let Err(e) = endpoint.address.to_socket_addrs() {
+            error!("Invalid pool address {}: {}", address, e);
+            std::process::exit(1);
+        }
+        pool_endpoints.push(endpoint);
     }
 
+    let tls_connector = if pool_endpoints.iter().any(|endpoint| endpoint.tls) {
+        let settings = TlsSettings {
+            ca_file: opt.tls_ca,
+            spki_pins: opt.tls_pin,
+            client_cert: opt.tls_cert,
+            client_key: opt.tls_key,
+        };
+        match tls::connector(&settings) {
+            Ok(r) => Some(r),
+            Err(e) => {
+                error!("Invalid tls settings: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    } else {
+        None
+    };
+    let outbox = match Outbox::open(&opt.state_dir, Duration::from_secs(opt.outbox_max_age)) {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Unable to open state directory {}: {}", opt.state_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let endpoints = PoolEndpoints::new(pool_endpoints, opt.failover_after, Duration::from_secs(opt.fallback_interval));
+
     info!("Starting taiko prover:");
 
-    let client = Client::init(access_key.clone(),unique_id, pool);
+    info!("Reporting {} cpus, {} gpus, {} GiB of memory to the pool", hardware.cpu, hardware.gpu, hardware.memory_gb);
+    let client = Client::init(access_key.clone(),unique_id, endpoints, opt.auth_backoff.map(Duration::from_secs), tls_connector, Duration::from_secs(opt.submit_timeout), Duration::from_secs(opt.disconnect_grace), outbox, hardware, opt.max_frame_length.max(DEFAULT_MAX_FRAME_LENGTH));
+
+    state.scheduler.set_capacity(Capacity {
+        slots: opt.slots.max(1),
+        cpu: threads as u32,
+        memory_gb: opt.max_memory,
+    });
+    info!("Proving with {} threads, {} slots", threads, opt.slots.max(1));
 
-    let prover: Arc<Prover> = match Prover::init(client.clone()).await {
+    let prover: Arc<Prover> = match Prover::init(client.clone(), pool, state.clone()).await {
         Ok(prover) => prover,
         Err(e) => {
             error!("Unable to initialize prover: {}", e);
@@ -147,7 +361,7 @@ async fn main() {
     };
     info!("Prover initialized");
 
-    start(prover.sender(), client.clone()).await;
+    start(prover.sender(), client.clone(), prover.state()).await;
 
     std::future::pending::<()>().await;
 }
@@ -1,166 +1,596 @@
-use std::io::Write;
+use std::io::{self, Read};
 
-use anyhow::anyhow;
 use byteorder::{LittleEndian, ReadBytesExt};
-use bytes::{Buf, BufMut, BytesMut};
-use snarkvm::{
-    dpc::{testnet2::Testnet2, Address, BlockTemplate, PoSWProof},
-    traits::Network,
-    utilities::{FromBytes, ToBytes},
+use bytes::{BufMut, BytesMut};
+use json_rpc_types::{Error, ErrorCode, Id};
+use taiko_stratum::{
+    codec::{BoxedType, ResponseParams, StratumCodec},
+    message::{HeartbeatStatus, StratumMessage},
+    proof::{self, Proof},
 };
 use tokio_util::codec::{Decoder, Encoder};
 
-#[allow(clippy::large_enum_variant)]
-pub enum ProverMessage {
-    // as in stratum, with an additional protocol version field
-    Authorize(Address<Testnet2>, String, u16),
-    AuthorizeResult(bool, Option<String>),
+/// Longest binary frame accepted unless the connection allows more
+pub const DEFAULT_MAX_BINARY_FRAME_LENGTH: usize = 1048576;
 
-    // combine notify and set_difficulty to be consistent
-    Notify(BlockTemplate<Testnet2>, u64),
-    // include block height to detect stales faster
-    Submit(u32, <Testnet2 as Network>::PoSWNonce, PoSWProof<Testnet2>),
-    // miners might want to know the stale rate, optionally provide a message
-    SubmitResult(bool, Option<String>),
+/// Length-prefixed binary framing of the zkpool messages, an alternative to JSON lines for pools
+/// that take large proofs: a u32 LE length, the message id, then the fields in order.
+/// Numbers are little endian, strings and byte arrays are prefixed by their u32 LE length,
+/// and submitted proofs go as raw bytes tagged with the format agreed with the pool, which the receiver
+/// encodes them back to. Fields a newer peer appends to a frame are ignored.
+pub struct BinaryCodec {
+    max_frame_length: usize,
+}
 
-    Canary,
+impl BinaryCodec {
+    pub fn new(max_frame_length: usize) -> Self {
+        Self { max_frame_length }
+    }
 }
 
-#[allow(dead_code)]
-static VERSION: u16 = 1;
+impl Default for BinaryCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_BINARY_FRAME_LENGTH)
+    }
+}
 
-impl ProverMessage {
-    #[allow(dead_code)]
-    pub fn version() -> &'static u16 {
-        &VERSION
+fn message_id(message: &StratumMessage) -> u8 {
+    match message {
+        StratumMessage::Subscribe(..) => 0,
+        StratumMessage::Authorize(..) => 1,
+        StratumMessage::Notify(..) => 2,
+        StratumMessage::Heartbeat(..) => 3,
+        StratumMessage::Submit(..) => 4,
+        StratumMessage::SubmitChunk(..) => 5,
+        StratumMessage::Response(..) => 6,
     }
+}
 
-    pub fn id(&self) -> u8 {
-        match self {
-            ProverMessage::Authorize(..) => 0,
-            ProverMessage::AuthorizeResult(..) => 1,
-            ProverMessage::Notify(..) => 2,
-            ProverMessage::Submit(..) => 3,
-            ProverMessage::SubmitResult(..) => 4,
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+/// Room for the message of a json-rpc error
+const ERROR_MESSAGE_LENGTH: usize = 31;
+
+//the pool's message is cut to fit, the error keeps the message of its code if empty
+fn pool_error(code: i64, message: &str) -> Error<()> {
+    let code = ErrorCode::from_code(code);
+    let mut end = message.len().min(ERROR_MESSAGE_LENGTH);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    if end == 0 {
+        return Error::from_code(code);
+    }
+    Error::with_custom_msg(code, &message[..end])
+}
+
+fn put_bytes(dst: &mut BytesMut, bytes: &[u8]) -> io::Result<()> {
+    let len = u32::try_from(bytes.len()).map_err(|_| invalid("Field too long"))?;
+    dst.put_u32_le(len);
+    dst.put_slice(bytes);
+    Ok(())
+}
+
+fn put_str(dst: &mut BytesMut, s: &str) -> io::Result<()> {
+    put_bytes(dst, s.as_bytes())
+}
 
-            ProverMessage::Canary => 5,
+fn put_strs(dst: &mut BytesMut, strs: &[String]) -> io::Result<()> {
+    dst.put_u32_le(strs.len() as u32);
+    for s in strs {
+        put_str(dst, s)?;
+    }
+    Ok(())
+}
+
+fn put_id(dst: &mut BytesMut, id: &Id) -> io::Result<()> {
+    match id {
+        Id::Num(id) => {
+            dst.put_u8(0);
+            dst.put_u64_le(*id);
+        }
+        Id::Str(id) => {
+            dst.put_u8(1);
+            put_str(dst, id)?;
         }
     }
+    Ok(())
+}
 
-    pub fn name(&self) -> &'static str {
-        match self {
-            ProverMessage::Authorize(..) => "Authorize",
-            ProverMessage::AuthorizeResult(..) => "AuthorizeResult",
-            ProverMessage::Notify(..) => "Notify",
-            ProverMessage::Submit(..) => "Submit",
-            ProverMessage::SubmitResult(..) => "SubmitResult",
+fn put_option<T>(dst: &mut BytesMut, value: Option<T>, put: impl FnOnce(&mut BytesMut, T)) {
+    match value {
+        Some(value) => {
+            dst.put_u8(1);
+            put(dst, value);
+        }
+        None => dst.put_u8(0),
+    }
+}
+
+/// Tags of a submitted proof: the string as given, or the raw instances and bytes of a proof
+/// in the legacy or versioned format.
+const PROOF_AS_GIVEN: u8 = 0;
+const PROOF_LEGACY: u8 = 1;
+const PROOF_V1: u8 = 2;
+
+//a proof goes as its instances and raw bytes if it encodes back to the same string, anything else as the string
+fn put_proof(dst: &mut BytesMut, encoded: &str) -> io::Result<()> {
+    let raw = match proof::decode_any(encoded) {
+        Ok(proof) if encoded.starts_with('{') && proof::encode(&proof) == encoded => Some((PROOF_V1, proof)),
+        Ok(proof) if !encoded.starts_with('{') && proof::encode_legacy(&proof) == encoded => Some((PROOF_LEGACY, proof)),
+        _ => None,
+    };
+    match raw {
+        Some((tag, proof)) => {
+            dst.put_u8(tag);
+            put_strs(dst, &proof.instances)?;
+            put_bytes(dst, &proof.proof)
+        }
+        None => {
+            dst.put_u8(PROOF_AS_GIVEN);
+            put_str(dst, encoded)
+        }
+    }
+}
 
-            ProverMessage::Canary => "Canary",
+fn put_response_params(dst: &mut BytesMut, result: Option<ResponseParams>) -> io::Result<()> {
+    match result {
+        None => dst.put_u8(0),
+        Some(ResponseParams::Null) => dst.put_u8(1),
+        Some(ResponseParams::Bool(b)) => {
+            dst.put_u8(2);
+            dst.put_u8(b as u8);
         }
+        Some(ResponseParams::Array(items)) => {
+            dst.put_u8(3);
+            dst.put_u32_le(items.len() as u32);
+            for item in &items {
+                put_response_item(dst, item.as_ref())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn put_response_item(dst: &mut BytesMut, item: &dyn BoxedType) -> io::Result<()> {
+    if let Some(s) = item.downcast_ref::<String>().or_else(|| item.downcast_ref::<Option<String>>().and_then(|s| s.as_ref())) {
+        dst.put_u8(1);
+        put_str(dst, s)
+    } else if let Some(Some(n)) = item.downcast_ref::<Option<u64>>() {
+        dst.put_u8(2);
+        dst.put_u64_le(*n);
+        Ok(())
+    } else if let Some(list) = item.downcast_ref::<Vec<String>>() {
+        dst.put_u8(3);
+        put_strs(dst, list)
+    } else {
+        dst.put_u8(0);
+        Ok(())
     }
 }
 
-impl Encoder<ProverMessage> for ProverMessage {
-    type Error = anyhow::Error;
+impl Encoder<StratumMessage> for BinaryCodec {
+    type Error = io::Error;
 
-    fn encode(&mut self, item: ProverMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
-        dst.extend_from_slice(&0u32.to_le_bytes());
-        let mut writer = dst.writer();
-        writer.write_all(&[item.id()])?;
+    fn encode(&mut self, item: StratumMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        let start = dst.len();
+        dst.put_u32_le(0);
+        dst.put_u8(message_id(&item));
         match item {
-            ProverMessage::Authorize(addr, password, version) => {
-                bincode::serialize_into(&mut writer, &addr)?;
-                bincode::serialize_into(&mut writer, &password)?;
-                writer.write_all(&version.to_le_bytes())?;
+            StratumMessage::Subscribe(id, user_agent, protocol_version, cpu, gpu, memory, capabilities) => {
+                put_id(dst, &id)?;
+                put_str(dst, &user_agent)?;
+                put_str(dst, &protocol_version)?;
+                dst.put_u64_le(cpu);
+                dst.put_u64_le(gpu);
+                dst.put_u64_le(memory);
+                put_strs(dst, &capabilities)?;
+            }
+            StratumMessage::Authorize(id, name, password) => {
+                put_id(dst, &id)?;
+                put_str(dst, &name)?;
+                put_str(dst, &password)?;
             }
-            ProverMessage::AuthorizeResult(result, message) | ProverMessage::SubmitResult(result, message) => {
-                writer.write_all(&[match result {
-                    true => 1,
-                    false => 0,
-                }])?;
-                if let Some(message) = message {
-                    writer.write_all(&[1])?;
-                    bincode::serialize_into(&mut writer, &message)?;
-                } else {
-                    writer.write_all(&[0])?;
+            StratumMessage::Notify(id, project_name, task_id, task_content, degree) => {
+                put_id(dst, &id)?;
+                put_str(dst, &project_name)?;
+                dst.put_u64_le(task_id);
+                put_str(dst, &task_content)?;
+                dst.put_u64_le(degree);
+            }
+            StratumMessage::Heartbeat(id, project_name, block, status) => {
+                put_id(dst, &id)?;
+                put_str(dst, &project_name)?;
+                put_str(dst, &block)?;
+                match status {
+                    Some(status) => {
+                        dst.put_u8(1);
+                        put_str(dst, &status.phase)?;
+                        dst.put_u64_le(status.elapsed_secs);
+                        put_option(dst, status.remaining_secs, |dst, v| dst.put_u64_le(v));
+                        put_option(dst, status.cpu_load, |dst, v| dst.put_f64_le(v));
+                        put_option(dst, status.free_memory_mb, |dst, v| dst.put_u64_le(v));
+                        dst.put_u64_le(status.active_slots);
+                    }
+                    None => dst.put_u8(0),
+                }
+            }
+            StratumMessage::Submit(id, project_name, block, proof, degree, time, status, reason) => {
+                put_id(dst, &id)?;
+                put_str(dst, &project_name)?;
+                put_str(dst, &block)?;
+                put_proof(dst, &proof)?;
+                dst.put_u8(degree);
+                dst.put_u32_le(time);
+                dst.put_u8(status);
+                match reason {
+                    Some(reason) => {
+                        dst.put_u8(1);
+                        put_str(dst, &reason)?;
+                    }
+                    None => dst.put_u8(0),
                 }
             }
-            ProverMessage::Notify(template, difficulty) => {
-                template.write_le(&mut writer)?;
-                writer.write_all(&difficulty.to_le_bytes())?;
+            StratumMessage::SubmitChunk(id, project_name, block, part, degree, time, status, index, total, checksum) => {
+                put_id(dst, &id)?;
+                put_str(dst, &project_name)?;
+                put_str(dst, &block)?;
+                put_str(dst, &part)?;
+                dst.put_u8(degree);
+                dst.put_u32_le(time);
+                dst.put_u8(status);
+                dst.put_u32_le(index);
+                dst.put_u32_le(total);
+                put_str(dst, &checksum)?;
             }
-            ProverMessage::Submit(height, nonce, proof) => {
-                writer.write_all(&height.to_le_bytes())?;
-                nonce.write_le(&mut writer)?;
-                proof.write_le(&mut writer)?;
+            StratumMessage::Response(id, result, error) => {
+                put_id(dst, &id)?;
+                put_response_params(dst, result)?;
+                match error {
+                    Some(error) => {
+                        dst.put_u8(1);
+                        dst.put_i64_le(error.code.code());
+                        put_str(dst, &error.message)?;
+                    }
+                    None => dst.put_u8(0),
+                }
             }
-            ProverMessage::Canary => return Err(anyhow!("Use of unsupported message")),
         }
-        let msg_len = dst.len() - 4;
-        dst[..4].copy_from_slice(&(msg_len as u32).to_le_bytes());
+        let msg_len = dst.len() - start - 4;
+        if msg_len > self.max_frame_length {
+            dst.truncate(start);
+            return Err(invalid("Message too long"));
+        }
+        dst[start..start + 4].copy_from_slice(&(msg_len as u32).to_le_bytes());
         Ok(())
     }
 }
 
-impl Decoder for ProverMessage {
-    type Error = anyhow::Error;
-    type Item = ProverMessage;
+/// Reads the fields of one frame, every read past its end is an error.
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn u8(&mut self) -> io::Result<u8> {
+        self.0.read_u8()
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        self.0.read_u32::<LittleEndian>()
+    }
+
+    fn u64(&mut self) -> io::Result<u64> {
+        self.0.read_u64::<LittleEndian>()
+    }
+
+    fn bytes(&mut self) -> io::Result<Vec<u8>> {
+        let len = self.u32()? as usize;
+        if len > self.0.len() {
+            return Err(invalid("Field past the end of the message"));
+        }
+        let mut bytes = vec![0; len];
+        self.0.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        String::from_utf8(self.bytes()?).map_err(|_| invalid("Field is not utf-8"))
+    }
+
+    fn strings(&mut self) -> io::Result<Vec<String>> {
+        let count = self.u32()?;
+        //every string takes at least its length
+        if count as usize > self.0.len() / 4 {
+            return Err(invalid("Field past the end of the message"));
+        }
+        (0..count).map(|_| self.string()).collect()
+    }
+
+    fn id(&mut self) -> io::Result<Id> {
+        match self.u8()? {
+            0 => Ok(Id::Num(self.u64()?)),
+            1 => serde_json::from_value(serde_json::Value::String(self.string()?)).map_err(|e| invalid(&e.to_string())),
+            _ => Err(invalid("Invalid id")),
+        }
+    }
+
+    fn option<T>(&mut self, read: impl FnOnce(&mut Self) -> io::Result<T>) -> io::Result<Option<T>> {
+        match self.u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(read(self)?)),
+            _ => Err(invalid("Invalid option")),
+        }
+    }
+
+    fn proof(&mut self) -> io::Result<String> {
+        let tag = self.u8()?;
+        if tag == PROOF_AS_GIVEN {
+            return self.string();
+        }
+        let proof = Proof { instances: self.strings()?, proof: self.bytes()? };
+        match tag {
+            PROOF_LEGACY => Ok(proof::encode_legacy(&proof)),
+            PROOF_V1 => Ok(proof::encode(&proof)),
+            _ => Err(invalid("Invalid proof")),
+        }
+    }
+
+    fn response_params(&mut self) -> io::Result<Option<ResponseParams>> {
+        Ok(match self.u8()? {
+            0 => None,
+            1 => Some(ResponseParams::Null),
+            2 => Some(ResponseParams::Bool(self.u8()? != 0)),
+            3 => {
+                let count = self.u32()?;
+                if count as usize > self.0.len() {
+                    return Err(invalid("Field past the end of the message"));
+                }
+                let mut items: Vec<Box<dyn BoxedType>> = Vec::new();
+                for _ in 0..count {
+                    match self.u8()? {
+                        0 => items.push(Box::new(None::<String>)),
+                        1 => items.push(Box::new(self.string()?)),
+                        2 => items.push(Box::new(Some(self.u64()?))),
+                        3 => items.push(Box::new(self.strings()?)),
+                        _ => return Err(invalid("Invalid response item")),
+                    }
+                }
+                Some(ResponseParams::Array(items))
+            }
+            _ => return Err(invalid("Invalid response params")),
+        })
+    }
+}
+
+impl Decoder for BinaryCodec {
+    type Error = io::Error;
+    type Item = StratumMessage;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
         if src.len() < 4 {
             return Ok(None);
         }
-        let length = u32::from_le_bytes(src[..4].try_into().unwrap()) as usize;
-        if length > 1048576 {
-            return Err(anyhow!("Message too long"));
+        let length = u32::from_le_bytes([src[0], src[1], src[2], src[3]]) as usize;
+        if length > self.max_frame_length {
+            return Err(invalid("Message too long"));
         }
         if src.len() < 4 + length {
+            src.reserve(4 + length - src.len());
             return Ok(None);
         }
-
-        let reader = &mut src.reader();
-        reader.read_u32::<LittleEndian>()?;
-        let msg_id = reader.read_u8()?;
-        let msg = match msg_id {
-            0 => {
-                let addr = bincode::deserialize_from(&mut *reader)?;
-                let password = bincode::deserialize_from(&mut *reader)?;
-                let version = reader.read_u16::<LittleEndian>()?;
-                ProverMessage::Authorize(addr, password, version)
-            }
-            1 => {
-                let result = reader.read_u8()? == 1;
-                let message = if reader.read_u8()? == 1 {
-                    Some(bincode::deserialize_from(reader)?)
-                } else {
-                    None
-                };
-                ProverMessage::AuthorizeResult(result, message)
-            }
-            2 => {
-                let template = BlockTemplate::<Testnet2>::read_le(&mut *reader)?;
-                let difficulty = reader.read_u64::<LittleEndian>()?;
-                ProverMessage::Notify(template, difficulty)
-            }
+        let frame = src.split_to(4 + length);
+        let mut fields = Fields(&frame[4..]);
+        let msg = match fields.u8()? {
+            0 => StratumMessage::Subscribe(
+                fields.id()?,
+                fields.string()?,
+                fields.string()?,
+                fields.u64()?,
+                fields.u64()?,
+                fields.u64()?,
+                fields.strings()?,
+            ),
+            1 => StratumMessage::Authorize(fields.id()?, fields.string()?, fields.string()?),
+            2 => StratumMessage::Notify(fields.id()?, fields.string()?, fields.u64()?, fields.string()?, fields.u64()?),
             3 => {
-                let height = reader.read_u32::<LittleEndian>()?;
-                let nonce = <Testnet2 as Network>::PoSWNonce::read_le(&mut *reader)?;
-                let proof = PoSWProof::<Testnet2>::read_le(&mut *reader)?;
-                ProverMessage::Submit(height, nonce, proof)
+                let id = fields.id()?;
+                let project_name = fields.string()?;
+                let block = fields.string()?;
+                let status = fields.option(|fields| {
+                    Ok(HeartbeatStatus {
+                        phase: fields.string()?,
+                        elapsed_secs: fields.u64()?,
+                        remaining_secs: fields.option(|fields| fields.u64())?,
+                        cpu_load: fields.option(|fields| fields.0.read_f64::<LittleEndian>())?,
+                        free_memory_mb: fields.option(|fields| fields.u64())?,
+                        active_slots: fields.u64()?,
+                    })
+                })?;
+                StratumMessage::Heartbeat(id, project_name, block, status)
             }
-            4 => {
-                let result = reader.read_u8()? == 1;
-                let message = if reader.read_u8()? == 1 {
-                    Some(bincode::deserialize_from(reader)?)
-                } else {
-                    None
-                };
-                ProverMessage::SubmitResult(result, message)
+            4 => StratumMessage::Submit(
+                fields.id()?,
+                fields.string()?,
+                fields.string()?,
+                fields.proof()?,
+                fields.u8()?,
+                fields.u32()?,
+                fields.u8()?,
+                fields.option(|fields| fields.string())?,
+            ),
+            5 => StratumMessage::SubmitChunk(
+                fields.id()?,
+                fields.string()?,
+                fields.string()?,
+                fields.string()?,
+                fields.u8()?,
+                fields.u32()?,
+                fields.u8()?,
+                fields.u32()?,
+                fields.u32()?,
+                fields.string()?,
+            ),
+            6 => {
+                let id = fields.id()?;
+                let result = fields.response_params()?;
+                let error = fields.option(|fields| {
+                    let code = fields.0.read_i64::<LittleEndian>()?;
+                    let message = fields.string()?;
+                    Ok(pool_error(code, &message))
+                })?;
+                StratumMessage::Response(id, result, error)
             }
-            _ => {
-                return Err(anyhow!("Unknown message id: {}", msg_id));
+            msg_id => {
+                return Err(invalid(&format!("Unknown message id: {}", msg_id)));
             }
         };
         Ok(Some(msg))
     }
 }
+
+/// Framing used with one pool endpoint.
+pub enum PoolCodec {
+    Stratum(StratumCodec),
+    Binary(BinaryCodec),
+}
+
+impl Encoder<StratumMessage> for PoolCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: StratumMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match self {
+            PoolCodec::Stratum(codec) => codec.encode(item, dst),
+            PoolCodec::Binary(codec) => codec.encode(item, dst),
+        }
+    }
+}
+
+impl Decoder for PoolCodec {
+    type Error = io::Error;
+    type Item = StratumMessage;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        match self {
+            PoolCodec::Stratum(codec) => codec.decode(src),
+            PoolCodec::Binary(codec) => codec.decode(src),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn roundtrip(message: StratumMessage) -> (BytesMut, StratumMessage) {
+        let mut codec = BinaryCodec::default();
+        let mut dst = BytesMut::new();
+        codec.encode(message, &mut dst).unwrap();
+        let encoded = dst.clone();
+        let decoded = codec.decode(&mut dst).unwrap().unwrap();
+        assert!(dst.is_empty());
+        (encoded, decoded)
+    }
+
+    fn submit(proof: String) -> StratumMessage {
+        StratumMessage::Submit(Id::Num(9), String::from("taikoA5"), String::from("42"), proof, 20, 300, 0, None)
+    }
+
+    #[test]
+    fn submit_proof_as_raw_bytes() {
+        let proof = Proof {
+            instances: vec![String::from("12345678901234567890")],
+            proof: vec![0xab; 30000],
+        };
+        for encoded_proof in [proof::encode_legacy(&proof), proof::encode(&proof)] {
+            let (encoded, decoded) = roundtrip(submit(encoded_proof.clone()));
+            //the proof bytes plus less than 100 bytes of framing, hex would double them
+            assert!(encoded.len() < 30100);
+            //and the receiver gets the proof in the format it was sent in
+            match decoded {
+                StratumMessage::Submit(Id::Num(9), project_name, block, decoded_proof, 20, 300, 0, None) => {
+                    assert_eq!((project_name.as_str(), block.as_str()), ("taikoA5", "42"));
+                    assert_eq!(decoded_proof, encoded_proof);
+                }
+                message => panic!("unexpected {}", message.name()),
+            }
+        }
+
+        //what does not encode back the same goes as given
+        for given in ["not a proof", "#1#0xABCD"] {
+            match roundtrip(submit(given.to_string())).1 {
+                StratumMessage::Submit(_, _, _, decoded_proof, ..) => assert_eq!(decoded_proof, given),
+                message => panic!("unexpected {}", message.name()),
+            }
+        }
+    }
+
+    #[test]
+    fn handshake_messages() {
+        let (_, decoded) = roundtrip(StratumMessage::Subscribe(
+            Id::Num(1),
+            String::from("zkpool-prover/0.0.1"),
+            String::from("ZkPoolStratum/1.0.0"),
+            16,
+            1,
+            64,
+            vec![String::from("progress")],
+        ));
+        assert!(matches!(decoded, StratumMessage::Subscribe(Id::Num(1), _, _, 16, 1, 64, capabilities) if capabilities == ["progress"]));
+
+        let result = ResponseParams::Array(vec![Box::new(String::from("session")), Box::new(vec![String::from("progress")])]);
+        let (_, decoded) = roundtrip(StratumMessage::Response(Id::Num(1), Some(result), None));
+        match decoded {
+            StratumMessage::Response(Id::Num(1), Some(result), None) => {
+                assert_eq!(result.capabilities(), Some(vec![String::from("progress")]));
+            }
+            message => panic!("unexpected {}", message.name()),
+        }
+    }
+
+    fn decode_error(message: &str) -> Error<()> {
+        let mut codec = BinaryCodec::default();
+        let mut src = BytesMut::new();
+        //response id 1, no result, an error with code -32000
+        src.put_u8(6);
+        put_id(&mut src, &Id::Num(1)).unwrap();
+        src.put_u8(0);
+        src.put_u8(1);
+        src.put_i64_le(-32000);
+        put_str(&mut src, message).unwrap();
+        let mut frame = BytesMut::new();
+        frame.put_u32_le(src.len() as u32);
+        frame.extend_from_slice(&src);
+        match codec.decode(&mut frame).unwrap().unwrap() {
+            StratumMessage::Response(Id::Num(1), None, Some(error)) => error,
+            message => panic!("unexpected {}", message.name()),
+        }
+    }
+
+    #[test]
+    fn decode_pool_errors() {
+        let error = decode_error("");
+        assert_eq!(error.code, ErrorCode::ServerError(-32000));
+        assert_eq!(error.message.as_str(), ErrorCode::ServerError(-32000).message());
+
+        let error = decode_error(&"é".repeat(40));
+        assert_eq!(error.message.as_str(), "é".repeat(15));
+
+        let error = decode_error("task 12 is not assigned to you, skip it");
+        assert_eq!(error.message.as_str(), "task 12 is not assigned to you,");
+    }
+
+    #[test]
+    fn reject_malformed_frames() {
+        let mut codec = BinaryCodec::new(64);
+        //a string longer than its frame
+        let mut src = BytesMut::from(&[6u8, 0, 0, 0, 1, 0, 0xff, 0xff, 0xff, 0xff][..]);
+        assert!(codec.decode(&mut src).is_err());
+        //longer than allowed
+        let mut src = BytesMut::from(&[0xffu8, 0xff, 0, 0][..]);
+        assert!(codec.decode(&mut src).is_err());
+        //incomplete
+        let mut src = BytesMut::from(&[8u8, 0, 0, 0, 1][..]);
+        assert!(codec.decode(&mut src).unwrap().is_none());
+    }
+}
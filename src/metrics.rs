@@ -0,0 +1,286 @@
+use std::{
+    collections::BTreeMap,
+    convert::Infallible,
+    fmt::Write,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use hyper::{
+    header::CONTENT_TYPE,
+    service::{make_service_fn, service_fn},
+    Body, Method, Request, Response, Server, StatusCode,
+};
+use tracing::{error, info};
+
+use crate::prover::ProverState;
+
+/// Upper bounds in seconds of the proof duration buckets.
+const PROOF_DURATION_BUCKETS: [f64; 9] = [30.0, 60.0, 120.0, 300.0, 600.0, 900.0, 1200.0, 1800.0, 3600.0];
+
+/// What happened to a task, counted per project.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskEvent {
+    Received,
+    Started,
+    Completed,
+    Failed,
+    /// proven but malformed or rejected by the verifier
+    Invalid,
+    Cancelled,
+}
+
+impl TaskEvent {
+    fn name(&self) -> &'static str {
+        match self {
+            TaskEvent::Received => "received",
+            TaskEvent::Started => "started",
+            TaskEvent::Completed => "completed",
+            TaskEvent::Failed => "failed",
+            TaskEvent::Invalid => "invalid",
+            TaskEvent::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Default)]
+struct Histogram {
+    buckets: [u64; PROOF_DURATION_BUCKETS.len()],
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    fn observe(&mut self, value: f64) {
+        for (bucket, bound) in self.buckets.iter_mut().zip(PROOF_DURATION_BUCKETS.iter()) {
+            if value <= *bound {
+                *bucket += 1;
+            }
+        }
+        self.count += 1;
+        self.sum += value;
+    }
+}
+
+/// Counters of one prover instance, rendered in the Prometheus text format.
+#[derive(Default)]
+pub struct Metrics {
+    //pool endpoint of the established session
+    connected: Mutex<Option<String>>,
+    reconnects: AtomicU64,
+    submit_accepted: AtomicU64,
+    submit_rejected: AtomicU64,
+    //heartbeat round trip in microseconds, 0 until the first response
+    heartbeat_rtt_micros: AtomicU64,
+    tasks: Mutex<BTreeMap<(String, TaskEvent), u64>>,
+    proof_duration: Mutex<BTreeMap<String, Histogram>>,
+    //local proof verifications by project and whether they passed
+    verifications: Mutex<BTreeMap<(String, bool), u64>>,
+    last_task: Mutex<Option<Instant>>,
+}
+
+impl Metrics {
+    pub fn set_connected(&self, endpoint: Option<&str>) {
+        *self.connected.lock().unwrap() = endpoint.map(str::to_string);
+    }
+
+    pub fn reconnected(&self) {
+        self.reconnects.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn submit_accepted(&self) {
+        self.submit_accepted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn submit_rejected(&self) {
+        self.submit_rejected.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn heartbeat_rtt(&self, rtt: Duration) {
+        self.heartbeat_rtt_micros.store(rtt.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    pub fn task(&self, project_name: &str, event: TaskEvent) {
+        *self
+            .tasks
+            .lock()
+            .unwrap()
+            .entry((project_name.to_string(), event))
+            .or_default() += 1;
+        if event == TaskEvent::Received {
+            *self.last_task.lock().unwrap() = Some(Instant::now());
+        }
+    }
+
+    pub fn proof_duration(&self, project_name: &str, duration: Duration) {
+        self.proof_duration
+            .lock()
+            .unwrap()
+            .entry(project_name.to_string())
+            .or_default()
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn verification(&self, project_name: &str, passed: bool) {
+        *self
+            .verifications
+            .lock()
+            .unwrap()
+            .entry((project_name.to_string(), passed))
+            .or_default() += 1;
+    }
+
+    /// Average time proving a task of the project, None before the first proof.
+    pub fn mean_proof_duration(&self, project_name: &str) -> Option<Duration> {
+        let proof_duration = self.proof_duration.lock().unwrap();
+        let histogram = proof_duration.get(project_name).filter(|histogram| histogram.count > 0)?;
+        Some(Duration::from_secs_f64(histogram.sum / histogram.count as f64))
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP zkpool_connected Whether a pool session is established, and to which endpoint");
+        let _ = writeln!(out, "# TYPE zkpool_connected gauge");
+        match &*self.connected.lock().unwrap() {
+            Some(endpoint) => {
+                let _ = writeln!(out, "zkpool_connected{{endpoint=\"{}\"}} 1", escape(endpoint));
+            }
+            None => {
+                let _ = writeln!(out, "zkpool_connected{{endpoint=\"\"}} 0");
+            }
+        }
+        counter(&mut out, "zkpool_reconnects_total", "Sessions established after the first one", self.reconnects.load(Ordering::Relaxed));
+
+        let _ = writeln!(out, "# HELP zkpool_tasks_total Tasks by project and outcome");
+        let _ = writeln!(out, "# TYPE zkpool_tasks_total counter");
+        for ((project, event), count) in self.tasks.lock().unwrap().iter() {
+            let _ = writeln!(out, "zkpool_tasks_total{{project=\"{}\",event=\"{}\"}} {}", escape(project), event.name(), count);
+        }
+
+        let _ = writeln!(out, "# HELP zkpool_proof_duration_seconds Time spent proving a task");
+        let _ = writeln!(out, "# TYPE zkpool_proof_duration_seconds histogram");
+        for (project, histogram) in self.proof_duration.lock().unwrap().iter() {
+            let project = escape(project);
+            for (bound, count) in PROOF_DURATION_BUCKETS.iter().zip(histogram.buckets.iter()) {
+                let _ = writeln!(out, "zkpool_proof_duration_seconds_bucket{{project=\"{}\",le=\"{}\"}} {}", project, bound, count);
+            }
+            let _ = writeln!(out, "zkpool_proof_duration_seconds_bucket{{project=\"{}\",le=\"+Inf\"}} {}", project, histogram.count);
+            let _ = writeln!(out, "zkpool_proof_duration_seconds_sum{{project=\"{}\"}} {}", project, histogram.sum);
+            let _ = writeln!(out, "zkpool_proof_duration_seconds_count{{project=\"{}\"}} {}", project, histogram.count);
+        }
+
+        let _ = writeln!(out, "# HELP zkpool_verifications_total Proofs verified before submitting by project and result");
+        let _ = writeln!(out, "# TYPE zkpool_verifications_total counter");
+        for ((project, passed), count) in self.verifications.lock().unwrap().iter() {
+            let result = if *passed { "passed" } else { "failed" };
+            let _ = writeln!(out, "zkpool_verifications_total{{project=\"{}\",result=\"{}\"}} {}", escape(project), result, count);
+        }
+
+        let _ = writeln!(out, "# HELP zkpool_submits_total Proofs answered by the pool");
+        let _ = writeln!(out, "# TYPE zkpool_submits_total counter");
+        let _ = writeln!(out, "zkpool_submits_total{{result=\"accepted\"}} {}", self.submit_accepted.load(Ordering::Relaxed));
+        let _ = writeln!(out, "zkpool_submits_total{{result=\"rejected\"}} {}", self.submit_rejected.load(Ordering::Relaxed));
+
+        let rtt = self.heartbeat_rtt_micros.load(Ordering::Relaxed);
+        if rtt > 0 {
+            gauge(&mut out, "zkpool_heartbeat_rtt_seconds", "Round trip of the last answered heartbeat", rtt as f64 / 1e6);
+        }
+        if let Some(last_task) = *self.last_task.lock().unwrap() {
+            gauge(&mut out, "zkpool_last_task_age_seconds", "Time since the last task was received", last_task.elapsed().as_secs_f64());
+        }
+        out
+    }
+}
+
+fn gauge(out: &mut String, name: &str, help: &str, value: f64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} gauge\n{} {}", name, help, name, name, value);
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}\n# TYPE {} counter\n{} {}", name, help, name, name, value);
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+/// Serve `/metrics` on `addr`, binding now so that a busy port fails at startup.
+pub fn serve(addr: SocketAddr, state: Arc<ProverState>) -> hyper::Result<SocketAddr> {
+    let make_service = make_service_fn(move |_| {
+        let state = state.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |request: Request<Body>| {
+                let state = state.clone();
+                async move { Ok::<_, Infallible>(respond(&state, &request)) }
+            }))
+        }
+    });
+    let server = Server::try_bind(&addr)?.serve(make_service);
+    let local_addr = server.local_addr();
+    info!("Serving metrics on http://{}/metrics", local_addr);
+    tokio::spawn(async move {
+        if let Err(e) = server.await {
+            error!("Metrics server stopped: {}", e);
+        }
+    });
+    Ok(local_addr)
+}
+
+fn respond(state: &ProverState, request: &Request<Body>) -> Response<Body> {
+    if request.method() != Method::GET || request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("not found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return response;
+    }
+    let mut response = Response::new(Body::from(state.metrics.render()));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::scheduler::Capacity;
+
+    #[tokio::test]
+    async fn scrape_metrics() {
+        let state = Arc::new(ProverState::new(Vec::new(), Capacity::default()));
+        state.metrics.set_connected(Some("tls://pool.example.com:18082"));
+        state.metrics.task("taikoA5", TaskEvent::Received);
+        state.metrics.task("taikoA5", TaskEvent::Completed);
+        state.metrics.proof_duration("taikoA5", Duration::from_secs(100));
+        state.metrics.verification("taikoA5", false);
+        state.metrics.task("taikoA5", TaskEvent::Invalid);
+        state.metrics.submit_accepted();
+
+        let addr = serve("127.0.0.1:0".parse().unwrap(), state).unwrap();
+        let mut socket = TcpStream::connect(addr).await.unwrap();
+        socket
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        socket.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.contains("zkpool_connected{endpoint=\"tls://pool.example.com:18082\"} 1\n"));
+        assert!(response.contains("zkpool_tasks_total{project=\"taikoA5\",event=\"completed\"} 1\n"));
+        assert!(response.contains("zkpool_proof_duration_seconds_bucket{project=\"taikoA5\",le=\"60\"} 0\n"));
+        assert!(response.contains("zkpool_proof_duration_seconds_bucket{project=\"taikoA5\",le=\"120\"} 1\n"));
+        assert!(response.contains("zkpool_verifications_total{project=\"taikoA5\",result=\"failed\"} 1\n"));
+        assert!(response.contains("zkpool_tasks_total{project=\"taikoA5\",event=\"invalid\"} 1\n"));
+        assert!(response.contains("zkpool_submits_total{result=\"accepted\"} 1\n"));
+        assert!(response.contains("zkpool_last_task_age_seconds "));
+    }
+}
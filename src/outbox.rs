@@ -0,0 +1,136 @@
+use std::{
+    fs,
+    io,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::submit::SubmitRecord;
+
+#[derive(Serialize, Deserialize)]
+struct OutboxEntry {
+    created_at: u64,
+    record: SubmitRecord,
+}
+
+/// Finished proofs kept on disk until the pool acknowledges them.
+pub struct Outbox {
+    dir: PathBuf,
+    max_age: Duration,
+}
+
+impl Outbox {
+    pub fn open(state_dir: &Path, max_age: Duration) -> io::Result<Self> {
+        let dir = state_dir.join("outbox");
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_age })
+    }
+
+    /// Persist a proof before it is sent, replacing an older proof of the same task.
+    pub fn store(&self, record: &SubmitRecord) -> io::Result<()> {
+        let entry = OutboxEntry {
+            created_at: now_secs(),
+            record: record.clone(),
+        };
+        let bytes = serde_json::to_vec(&entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        //write then rename so that a crash never leaves a truncated entry
+        let path = self.path(record);
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, bytes)?;
+        fs::rename(tmp, path)
+    }
+
+    /// The pool acknowledged the proof.
+    pub fn remove(&self, record: &SubmitRecord) {
+        if let Err(e) = fs::remove_file(self.path(record)) {
+            if e.kind() != io::ErrorKind::NotFound {
+                warn!("Failed to remove the {} proof of task {} from outbox: {}", record.project_name, record.task_id, e);
+            }
+        }
+    }
+
+    /// Proofs still waiting for an acknowledgement, stale and unreadable entries are deleted.
+    pub fn load(&self) -> Vec<SubmitRecord> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Failed to read outbox {}: {}", self.dir.display(), e);
+                return Vec::new();
+            }
+        };
+        let mut records = Vec::new();
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().map_or(true, |ext| ext != "json") {
+                continue;
+            }
+            let entry = match fs::read(&path).ok().and_then(|bytes| serde_json::from_slice::<OutboxEntry>(&bytes).ok()) {
+                Some(r) => r,
+                None => {
+                    warn!("Drop unreadable outbox entry {}", path.display());
+                    let _ = fs::remove_file(&path);
+                    continue;
+                }
+            };
+            if now_secs().saturating_sub(entry.created_at) > self.max_age.as_secs() {
+                info!("Drop the stale {} proof of task {} from outbox", entry.record.project_name, entry.record.task_id);
+                let _ = fs::remove_file(&path);
+                continue;
+            }
+            records.push((entry.created_at, entry.record));
+        }
+        records.sort_by_key(|(created_at, _)| *created_at);
+        records.into_iter().map(|(_, record)| record).collect()
+    }
+
+    fn path(&self, record: &SubmitRecord) -> PathBuf {
+        let project: String = record
+            .project_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '_' { c } else { '_' })
+            .collect();
+        let task_id: String = record.task_id.chars().filter(|c| c.is_ascii_alphanumeric()).collect();
+        self.dir.join(format!("{}-{}.json", project, task_id))
+    }
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(task_id: &str) -> SubmitRecord {
+        SubmitRecord {
+            project_name: "taikoA5".to_string(),
+            task_id: task_id.to_string(),
+            proof: "#inst#proof".to_string(),
+            degree: 21,
+            time: 30,
+            status: 1,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn store_load_and_remove() {
+        let state_dir = std::env::temp_dir().join(format!("zkpool-outbox-{}", std::process::id()));
+        let outbox = Outbox::open(&state_dir, Duration::from_secs(60)).unwrap();
+        outbox.store(&record("1")).unwrap();
+        outbox.store(&record("2")).unwrap();
+        fs::write(outbox.dir.join("broken.json"), b"{").unwrap();
+
+        let loaded = outbox.load();
+        assert_eq!(loaded.len(), 2);
+        assert!(!outbox.dir.join("broken.json").exists());
+
+        outbox.remove(&loaded[0]);
+        assert_eq!(outbox.load().len(), 1);
+        fs::remove_dir_all(state_dir).unwrap();
+    }
+}
@@ -1,5 +1,9 @@
-use std::thread::{self, JoinHandle};
+use std::future::Future;
 use std::sync::{Arc, mpsc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use tokio::{runtime::Handle, sync::oneshot};
+use tracing::debug;
 
 type Job = Box<dyn FnOnce() + 'static + Send>;
 enum Message {
@@ -8,46 +12,52 @@ enum Message {
 }
 
 
-struct Worker where
+struct Worker
 {
-    id: usize,
     t: Option<JoinHandle<()>>,
 }
 
 impl Worker
 {
     fn new(id: usize, receiver: Arc::<Mutex<mpsc::Receiver<Message>>>) -> Worker {
-        let t = thread::spawn( move || {
+        let t = thread::Builder::new().name(format!("prover-worker-{}", id)).spawn( move || {
             loop {
-                let message = receiver.lock().unwrap().recv().unwrap();
+                //the lock is released before running the job so that idle workers keep receiving
+                let message = match receiver.lock().unwrap().recv() {
+                    Ok(r) => r,
+                    Err(_) => break,
+                };
                 match message {
                     Message::NewJob(job) => {
-                        println!("do job from worker[{}]", id);
+                        debug!("do job from worker[{}]", id);
                         job();
                     },
                     Message::ByeBye => {
-                        println!("ByeBye from worker[{}]", id);
+                        debug!("ByeBye from worker[{}]", id);
                         break
                     },
-                }  
+                }
             }
-        });
+        }).expect("failed to spawn prover worker");
 
         Worker {
-            id,
             t: Some(t),
         }
     }
 }
 
+/// Dedicated threads for proving, so that a long proof never blocks the networking runtime.
+/// At most `max_workers` jobs run at once, the others wait in order.
 pub struct Pool {
     workers: Vec<Worker>,
     max_workers: usize,
-    sender: Arc<Mutex<mpsc::Sender<Message>>>
+    sender: Arc<Mutex<mpsc::Sender<Message>>>,
+    runtime: Handle,
 }
 
-impl Pool where {
-    pub fn new(max_workers: usize) -> Pool {
+impl Pool {
+    /// `runtime` drives the io of the jobs, e.g. the rpc requests made while proving.
+    pub fn new(max_workers: usize, runtime: Handle) -> Pool {
         if max_workers == 0 {
             panic!("max_workers must be greater than zero!")
         }
@@ -59,48 +69,79 @@ impl Pool where {
             workers.push(Worker::new(i, Arc::clone(&receiver))); //start the worker
         }
 
-        Pool { workers, max_workers, sender: Arc::new(Mutex::new(tx)) }
+        Pool { workers, max_workers, sender: Arc::new(Mutex::new(tx)), runtime }
     }
 
     pub fn execute<F>(&self, f:F) where F: FnOnce() + 'static + Send  //send the task
     {
 
         let job = Message::NewJob(Box::new(f));
-        let sender = self.sender.clone();
-        let sender = sender.lock().unwrap();
+        let sender = self.sender.lock().unwrap();
         sender.send(job).unwrap()
     }
+
+    /// Run `future` to completion on a worker, the result is dropped if the receiver is.
+    pub fn spawn<F>(&self, future: F) -> oneshot::Receiver<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (tx, rx) = oneshot::channel();
+        let runtime = self.runtime.clone();
+        self.execute(move || {
+            if tx.is_closed() {
+                //cancelled while waiting for a worker
+                return;
+            }
+            let _ = tx.send(runtime.block_on(future));
+        });
+        rx
+    }
 }
 
 impl Drop for Pool {
     fn drop(&mut self) {
-        for _ in 0..self.max_workers {
-            let sender = self.sender.clone();
-            let sender = sender.lock().unwrap();
-            sender.send(Message::ByeBye).unwrap();
-            // self.sender.send(Message::ByeBye).unwrap();
+        {
+            let sender = self.sender.lock().unwrap();
+            for _ in 0..self.max_workers {
+                let _ = sender.send(Message::ByeBye);
+            }
         }
         for w in self.workers.iter_mut() {
             if let Some(t) = w.t.take() {
-                t.join().unwrap();
+                let _ = t.join();
             }
         }
     }
-
-    #[cfg(test)]
-    mod tests {
-    use super::*;
-    #[test]
-    fn it_works() {
-        let p = Pool::new(4);
-        p.execute(|| println!("do new job1"));
-        p.execute(|| println!("do new job2"));
-        p.execute(|| println!("do new job3"));
-        p.execute(|| println!("do new job4"));
-     }
-    }
-
 }
 
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
 
+    use super::*;
 
+    #[tokio::test(flavor = "multi_thread")]
+    async fn bound_concurrency() {
+        let pool = Pool::new(2, Handle::current());
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let mut results = Vec::new();
+        for i in 0..6 {
+            let running = running.clone();
+            let max_running = max_running.clone();
+            results.push(pool.spawn(async move {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+                running.fetch_sub(1, Ordering::SeqCst);
+                i
+            }));
+        }
+        for (i, result) in results.into_iter().enumerate() {
+            assert_eq!(result.await.unwrap(), i);
+        }
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+}
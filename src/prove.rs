@@ -0,0 +1,206 @@
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::PathBuf,
+    time::Instant,
+};
+
+use anyhow::{anyhow, Context};
+use clap::Args;
+use serde::Serialize;
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
+
+use crate::pool::Pool;
+use crate::prover::{check_on, check_status, prove_on, ProjectInfo, PROOF_STATUS_FAILED, PROOF_STATUS_OK};
+
+/// Prove one task without a pool, e.g. to debug a failing block.
+#[derive(Debug, Args)]
+pub struct ProveOpt {
+    /// Project of the task, as named in the project config
+    #[clap(long = "project")]
+    project: String,
+
+    /// Task id, the block number for taiko projects
+    #[clap(long = "task_id", alias = "task-id")]
+    task_id: u64,
+
+    /// File with the task content as sent by the pool in `zkpool.notify`
+    #[clap(long = "task_file", alias = "task-file", conflicts_with = "task", required_unless_present = "task")]
+    task_file: Option<PathBuf>,
+
+    /// Task content inline instead of --task_file
+    #[clap(long = "task")]
+    task: Option<String>,
+
+    /// Write the result to this file instead of stdout
+    #[clap(long = "output")]
+    output: Option<PathBuf>,
+
+    /// Verify the proof even if the project config does not
+    #[clap(long = "verify")]
+    verify: bool,
+}
+
+/// Outcome of a local proof, with the fields `zkpool.submit` would carry.
+#[derive(Debug, Serialize)]
+pub struct ProveReport {
+    pub project: String,
+    pub task_id: u64,
+    pub status: u8,
+    /// whole seconds, as submitted to the pool
+    pub time: u32,
+    pub time_ms: u64,
+    pub degree: u8,
+    pub instances: Vec<String>,
+    /// the proof as submitted to the pool
+    pub proof: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Parse and prove the task like a task received from the pool.
+/// Fails if the task can't be read or parsed, a failed proof is reported with `PROOF_STATUS_FAILED`
+/// and a malformed or rejected one with `PROOF_STATUS_INVALID`.
+pub async fn run(opt: &ProveOpt, projects: &[ProjectInfo], pool: &Pool) -> anyhow::Result<ProveReport> {
+    let project = projects.iter().find(|project| project.name == opt.project).ok_or_else(|| {
+        let names: Vec<&str> = projects.iter().map(|project| project.name.as_str()).collect();
+        anyhow!("project {} is not configured, configured: {}", opt.project, names.join(", "))
+    })?;
+    let task_content = match (&opt.task, &opt.task_file) {
+        (Some(task), _) => task.clone(),
+        (None, Some(path)) => std::fs::read_to_string(path)
+            .with_context(|| format!("read task file {}", path.display()))?
+            .trim_end()
+            .to_string(),
+        (None, None) => return Err(anyhow!("--task or --task_file is required")),
+    };
+    let input = project
+        .backend
+        .parse(opt.task_id, &task_content)
+        .with_context(|| format!("{} task {} parameter error", project.name, opt.task_id))?;
+
+    info!("Prove {} task {}", project.name, opt.task_id);
+    let time_started = Instant::now();
+    let result = prove_on(pool, project, opt.task_id, input, CancellationToken::new()).await;
+    let elapsed = time_started.elapsed();
+    let (output, status, error) = match result {
+        Ok(r) if opt.verify || project.verify => {
+            let check = check_on(pool, project, opt.task_id, r.clone()).await;
+            //keep the proof for debugging
+            let (status, reason) = check_status(&check);
+            if let Some(reason) = &reason {
+                error!("{} proof of task {} is invalid: {}", project.name, opt.task_id, reason);
+            }
+            (r, status, reason)
+        }
+        Ok(r) => (r, PROOF_STATUS_OK, None),
+        Err(e) => {
+            error!("{} proof of task {} failed: {:#}", project.name, opt.task_id, e);
+            (Default::default(), PROOF_STATUS_FAILED, Some(format!("{:#}", e)))
+        }
+    };
+    info!("{} task {} proven in {} ms", project.name, opt.task_id, elapsed.as_millis());
+    Ok(ProveReport {
+        project: project.name.clone(),
+        task_id: opt.task_id,
+        status,
+        time: elapsed.as_secs() as u32,
+        time_ms: elapsed.as_millis() as u64,
+        degree: output.k,
+        proof: project.backend.serialize(&output),
+        instances: output.instances,
+        error,
+    })
+}
+
+/// Write the report as json to `--output`, or stdout.
+pub fn write(opt: &ProveOpt, report: &ProveReport) -> anyhow::Result<()> {
+    let mut out: Box<dyn Write> = match &opt.output {
+        Some(path) => Box::new(File::create(path).with_context(|| format!("create {}", path.display()))?),
+        None => Box::new(io::stdout()),
+    };
+    serde_json::to_writer_pretty(&mut out, report)?;
+    writeln!(out)?;
+    out.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use futures::future::BoxFuture;
+    use tokio::runtime::Handle;
+
+    use super::*;
+    use crate::backend::{ProofBackend, ProofOutput, TaskInput};
+
+    /// Proves tasks whose content is a number, fails the others while proving.
+    struct EchoBackend;
+
+    impl ProofBackend for EchoBackend {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+
+        fn parse(&self, _task_id: u64, task_content: &str) -> anyhow::Result<TaskInput> {
+            Ok(Box::new(task_content.to_string()))
+        }
+
+        fn prove(
+            &self,
+            _project: &ProjectInfo,
+            task_id: u64,
+            input: TaskInput,
+            _cancel: CancellationToken,
+        ) -> BoxFuture<'static, anyhow::Result<ProofOutput>> {
+            Box::pin(async move {
+                let content = input.downcast::<String>().unwrap();
+                let value: u8 = content.parse()?;
+                Ok(ProofOutput { instances: vec![task_id.to_string()], proof: vec![value], k: 20 })
+            })
+        }
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn prove_without_pool() {
+        let backend = Arc::new(EchoBackend);
+        let projects = vec![ProjectInfo {
+            name: "echo".to_string(),
+            rpc_url: String::new(),
+            backend: backend.clone(),
+            cost: backend.default_cost(),
+            verify: false,
+            verifier: None,
+        }];
+        let pool = Pool::new(1, Handle::current());
+        let mut opt = ProveOpt {
+            project: "echo".to_string(),
+            task_id: 7,
+            task_file: None,
+            task: Some("171".to_string()),
+            output: None,
+            verify: false,
+        };
+
+        let report = run(&opt, &projects, &pool).await.unwrap();
+        assert_eq!((report.status, report.degree, report.error), (PROOF_STATUS_OK, 20, None));
+        assert_eq!(report.instances, ["7"]);
+        assert!(report.proof.contains(r#""proof":"qw==""#));
+
+        //one byte is not a transcript of 32 bytes words
+        opt.verify = true;
+        let report = run(&opt, &projects, &pool).await.unwrap();
+        assert_eq!(report.status, crate::prover::PROOF_STATUS_INVALID);
+        assert!(report.error.unwrap().starts_with("malformed proof"));
+
+        opt.task = Some("not a number".to_string());
+        let report = run(&opt, &projects, &pool).await.unwrap();
+        assert_eq!(report.status, PROOF_STATUS_FAILED);
+        assert!(report.error.is_some());
+
+        opt.project = "taikoA5".to_string();
+        assert!(run(&opt, &projects, &pool).await.is_err());
+    }
+}
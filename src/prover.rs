@@ -3,60 +3,113 @@ use std::sync::{
         Arc,
     };
 
-use log::debug;
 use taiko_stratum::message::StratumMessage;
 use json_rpc_types::Id;
 
-use lazy_static::lazy_static;
 use tokio::sync::Mutex;
 
 use tokio::{
     sync::mpsc,
-    task::{self, JoinHandle},
+    task,
 };
-use tracing::{error, info};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info, warn};
 
 use crate::Client;
+use crate::metrics::{Metrics, TaskEvent};
+use crate::pool::Pool;
 
 use std::collections::HashMap;
 
 
 use std::time::Instant;
 
-//taiko A5 testnet lib core
-use zkevm_common::prover::ProofResult;
-use prover::shared_state::generate_proof;
+use crate::backend::{self, ProofBackend, ProofCheck, ProofOutput, TaskInput};
+use crate::scheduler::{Capacity, Scheduler, TaskCost, TaskPhase};
+use crate::submit::SubmitRecord;
 
-use serde::{Serialize, Deserialize};
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ProverConfig {
-    name_list: Vec<String>,
-    rpc_url_list: Vec<String>,
-}
-
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Clone)]
 pub struct ProjectInfo {
     pub name: String,
     pub rpc_url: String,
+    pub backend: Arc<dyn ProofBackend>,
+    pub cost: TaskCost,
+    /// verify proofs before submitting them
+    pub verify: bool,
+    /// deployment code of the project's verifier contract
+    pub verifier: Option<Arc<Vec<u8>>>,
 }
 
 
-lazy_static! {
-    pub static ref LATEST_TASK_CONTENT: Arc<Mutex<String>> = {
-        Arc::new(Mutex::new(String::from("")))
-    };
-    pub static ref TASK_HANDLER: Arc<Mutex<Vec<JoinHandle<()>>>> = {
-        Arc::new(Mutex::new(Vec::<JoinHandle<()>>::new()))
-    };
-    pub static ref PROJECT_LIST: Arc<Mutex<HashMap<String, ProjectInfo>>> = {
-        Arc::new(Mutex::new(HashMap::default()))
-    };
+/// State of one prover instance, shared by its `Prover` and `Client`.
+pub struct ProverState {
+    /// projects served, keyed by name
+    pub projects: Mutex<HashMap<String, ProjectInfo>>,
+    /// tasks received from the pool
+    pub scheduler: Scheduler,
+    pub metrics: Metrics,
+}
+
+impl ProverState {
+    pub fn new(projects: Vec<ProjectInfo>, capacity: Capacity) -> Self {
+        Self {
+            projects: Mutex::new(projects.into_iter().map(|project| (project.name.clone(), project)).collect()),
+            scheduler: Scheduler::new(capacity),
+            metrics: Metrics::default(),
+        }
+    }
+
+    pub async fn project(&self, name: &str) -> Option<ProjectInfo> {
+        self.projects.lock().await.get(name).cloned()
+    }
+
+    /// Cancel every task, e.g. when the pool connection is lost.
+    pub fn cancel_tasks(&self) {
+        self.scheduler.cancel_all();
+    }
+}
+
+/// `zkpool.submit` status of a computed proof
+pub const PROOF_STATUS_OK: u8 = 1;
+/// `zkpool.submit` status of a task that could not be proven
+pub const PROOF_STATUS_FAILED: u8 = 0;
+/// `zkpool.submit` status of a proof that is malformed or failed verification, submitted without the proof
+pub const PROOF_STATUS_INVALID: u8 = 2;
+
+/// Prove a parsed task of `project` on a worker of `pool`.
+pub async fn prove_on(pool: &Pool, project: &ProjectInfo, task_id: u64, input: TaskInput, cancel: CancellationToken) -> anyhow::Result<ProofOutput> {
+    match pool.spawn(project.backend.prove(project, task_id, input, cancel)).await {
+        Ok(r) => r,
+        Err(_) => Err(anyhow::anyhow!("prover worker stopped")),
+    }
+}
+
+/// Check a proof of `project` on a worker of `pool`.
+pub async fn check_on(pool: &Pool, project: &ProjectInfo, task_id: u64, output: ProofOutput) -> ProofCheck {
+    let project = project.clone();
+    match pool.spawn(async move { backend::check(&project, task_id, &output) }).await {
+        Ok(r) => r,
+        Err(_) => {
+            warn!("prover worker stopped, the proof of task {} is not checked", task_id);
+            ProofCheck::Unverified
+        }
+    }
+}
+
+/// Status and reason to submit a checked proof with.
+pub fn check_status(check: &ProofCheck) -> (u8, Option<String>) {
+    match check {
+        ProofCheck::Verified | ProofCheck::Unverified => (PROOF_STATUS_OK, None),
+        ProofCheck::Malformed(e) => (PROOF_STATUS_INVALID, Some(format!("malformed proof: {:#}", e))),
+        ProofCheck::Rejected(e) => (PROOF_STATUS_INVALID, Some(format!("verification failed: {:#}", e))),
+    }
 }
 
 pub struct Prover {
     sender: Arc<mpsc::Sender<ProverEvent>>,
     client: Arc<Client>,
+    pool: Arc<Pool>,
+    state: Arc<ProverState>,
     current_block: Arc<AtomicU64>,
 }
 
@@ -68,6 +121,8 @@ pub enum ProverEvent {
 impl Prover {
     pub async fn init(
         client: Arc<Client>,
+        pool: Arc<Pool>,
+        state: Arc<ProverState>,
     ) -> Result<Arc<Self>,String> {
 
         let (sender, mut receiver) = mpsc::channel(4096);
@@ -75,6 +130,8 @@ impl Prover {
         let prover = Arc::new(Self {
             sender: Arc::new(sender),
             client,
+            pool,
+            state,
             current_block: Default::default(),
         });
 
@@ -83,29 +140,6 @@ impl Prover {
             while let Some(msg) = receiver.recv().await {
                 match msg {
                        ProverEvent::NewWork(project,task_id,task_content) => {    
-                            //clear the older task handle,to be optimize
-                            let task_temp = TASK_HANDLER.clone();
-                            let queue = task_temp.lock().await;
-                            if queue.len()>0 {
-                                let _ = task::spawn(async move {
-                                    let task_temp = TASK_HANDLER.clone();
-                                   let mut queue = task_temp.lock().await;
-                                   if queue.len()>0 {
-                                       let task_handle =&(*queue)[0];
-                                       task_handle.abort();
-                                       drop(task_handle);
-                                       queue.remove(0);
-                                   }
-                                   debug!("clear the old task over");
-                               });
-                            }
-
-                            //Cache the newest block number
-                            let cached_task=format!("{}#{}",project,task_id);
-                            let current_task = LATEST_TASK_CONTENT.clone();
-                            let mut current_task_content = current_task.lock().await;
-                            *current_task_content = cached_task;
-
                             //compute the proof
                             p.new_work(    //work
                                 project,
@@ -125,141 +159,147 @@ impl Prover {
         self.sender.clone()
     }
 
+    pub fn state(&self) -> Arc<ProverState> {
+        self.state.clone()
+    }
+
     async fn new_work(&self,project_name:String, block: u64, task_content: String) {
         self.current_block.store(block, Ordering::SeqCst);
         let client = self.client.clone();
-        let project_map = PROJECT_LIST.lock().await;
-        let project_map_info = (*project_map).clone();
-
-        let project_info = match project_map_info.get(&project_name.clone()){
-            Some(r) => r.clone(),
+        //the task keeps this copy even if the projects are reloaded meanwhile
+        let project_info = match self.state.project(&project_name).await {
+            Some(r) => r,
             None => {
                 error!("can find this project {} info,ignore it",project_name.clone());
                 return
             },
         };
+        //counted for configured projects only, the project label can't take any name the pool sends
+        self.state.metrics.task(&project_name, TaskEvent::Received);
 
         info!("receive task,project name is:{},task id is:{},task content is:{}",project_name.clone(),block,task_content);
 
-        if project_name.clone()=="taikoA5".to_string(){
-            let l2_rpc = project_info.rpc_url;
-            let task_vec: Vec<&str> = task_content.split("#").collect(); //Parse the task content
-            if task_vec.len() != 14{
-                error!("{} task parameter error,ignore it",project_name.clone());
+        let backend = project_info.backend.clone();
+        let task_input = match backend.parse(block, &task_content) {
+            Ok(r) => r,
+            Err(e) => {
+                //report the malformed task before any proving work
+                error!("{} task {} parameter error: {},report it as failed",project_name.clone(),block,e);
+                self.state.metrics.task(&project_name, TaskEvent::Failed);
+                let message = StratumMessage::Submit(
+                    Id::Num(0),
+                    project_name.clone(),
+                    block.to_string(),
+                    String::from(""),
+                    0,
+                    0,
+                    PROOF_STATUS_FAILED,
+                    None,
+                );
+                if let Err(error) = client.sender().send(message).await {
+                    error!("Failed to send PoolResponse: {}", error);
+                }
                 return
-            }
-            let prover_address=task_vec[0].to_string();
-            let l1_signal_service=task_vec[1].to_string();
-            let l2_signal_service=task_vec[2].to_string();
-            let taiko_12=task_vec[3].to_string();
-            let meta_hash=task_vec[4].to_string();
-            let blockhash=task_vec[5].to_string();
-            let parenthash=task_vec[6].to_string();
-            let signalroot=task_vec[7].to_string();
-            let graffiti=task_vec[8].to_string();
-
-            let gasused=task_vec[9].to_string().parse::<u64>().unwrap();
-            let parentgasused=task_vec[10].parse::<u64>().unwrap();
-            let blockmaxgasimit=task_vec[11].parse::<u64>().unwrap();
-            let maxtransactionsperblock=task_vec[12].parse::<u64>().unwrap();
-            let maxbytespertxlist=task_vec[13].parse::<u64>().unwrap();
-
-            
-            let _ = task::spawn(async move { //maybe multi-thread compute task in future
-    
-                let task_handle = task::spawn(async move {
-                    let mut status:u8=1;
-                    let time_started = Instant::now();
-                    let agg_proof_result = match generate_proof(
-                    l2_rpc,
-                    block,
-                    prover_address.clone(),
-                    l1_signal_service.clone(),
-                    l2_signal_service.clone(),
-                    taiko_12.clone(),
-                    meta_hash.clone(),
-                    blockhash.clone(),
-                    parenthash.clone(),
-                    signalroot.clone(),
-                    graffiti.clone(),
-                    gasused,
-                    parentgasused,
-                    blockmaxgasimit,
-                    maxtransactionsperblock,
-                    maxbytespertxlist).await{
-                        Ok(r) => r,
-                        Err(_) => {
-                            status=0;
-                            ProofResult::default()
-                        },
-                    };                  
-                    let time_gap =(Instant::now().duration_since(time_started).as_millis() as u32)/1000;
-                    info!("try to sumbit the block {} proof to zkpool,proof is {:?},time consumed:{}",block,agg_proof_result,time_gap);
-
-                    if need_send_proof(project_name.clone(), block).await {
-                        let mut proofoutput =String::from("");
-                        for var in &agg_proof_result.instance{
-                            proofoutput=format!("{}#{}",proofoutput,var.to_string())
-                        }
-                        let proof_res = format!("{}#{}",proofoutput,agg_proof_result.proof);
-                        let message = StratumMessage::Submit(
-                            Id::Num(0),
-                            project_name.clone(),
-                            block.to_string(),
-                            proof_res,
-                            agg_proof_result.k,
-                            time_gap,
-                            status,
-                        );
-                        if let Err(error) = client.sender().send(message).await { 
-                            error!("Failed to send PoolResponse: {}", error);
-                        }else{
-                            info!("zkpool:send the proof of block:{} success,time consumed:{}",block,time_gap);
-                        }
-                        info!("zkpool:end computed the task of block:{}",block);
-
-                        let current_task = LATEST_TASK_CONTENT.clone();
-                        let mut current_task_content = current_task.lock().await;
-                        *current_task_content = String::from("");
-
-                    }  
-                });
-    
-                // cache the task handle
-                let task_handle_vec = TASK_HANDLER.clone();
-                let mut queue = task_handle_vec.lock().await;
-                queue.push(task_handle);
-            });
-        }else{
-            info!("ignore the unrecognized {} task",project_name);
-        }
-        info!("******one block task in process********");
-    }
-}
+            },
+        };
 
+        let state = self.state.clone();
+        let cancel = match state.scheduler.enqueue(&project_name, block, project_info.cost) {
+            Some(r) => r,
+            None => {
+                info!("{} task {} is already in process,ignore it",project_name,block);
+                return
+            },
+        };
 
-pub async fn need_send_proof(project:String,block:u64) -> bool {
-    let current_task = LATEST_TASK_CONTENT.clone();
-    let current_task_content = current_task.lock().await;
-    if *current_task_content==String::from(""){ //initial
-        return true
-    }else {
-        let task_vec: Vec<&str> = (*current_task_content).split("#").collect();
-        if task_vec.len() != 2{
-            return true
-        }else {
-            let project_now=task_vec[0];
-            let cached_block = task_vec[1].parse::<u64>().unwrap();
-            info!("cached task info is:{}+{}",project_now,cached_block);
-            if project==project_now{
-                if block>=cached_block{
-                    return true
-                }else {
-                    return false
+        let pool = self.pool.clone();
+        let _ = task::spawn(async move {
+            //wait until the task fits in the free slots
+            let permit = match state.scheduler.admit(&project_name, block).await {
+                Some(r) => r,
+                None => {
+                    info!("{} task {} cancelled before proving",project_name,block);
+                    state.metrics.task(&project_name, TaskEvent::Cancelled);
+                    return
+                },
+            };
+            state.metrics.task(&project_name, TaskEvent::Started);
+            let mut status:u8=PROOF_STATUS_OK;
+            let time_started = Instant::now();
+            //prove on the worker pool, the runtime stays free for the pool connection
+            let proof_result = prove_on(&pool, &project_info, block, task_input, cancel.clone()).await;
+            let proved_in = time_started.elapsed();
+            if cancel.is_cancelled() {
+                //the worker has stopped, only now the slot is free again.
+                //a proof that finished just before the cancel is dropped
+                drop(permit);
+                match proof_result {
+                    Ok(_) => info!("{} task {} cancelled after its proof finished,the proof is dropped",project_name,block),
+                    Err(_) => info!("{} task {} cancelled,its proof has stopped",project_name,block),
                 }
-            }else {
-                return false
+                state.metrics.task(&project_name, TaskEvent::Cancelled);
+                return
             }
-        }
+            let mut reason = None;
+            let agg_proof_result = match proof_result{
+                Ok(r) => {
+                    if project_info.verify {
+                        permit.set_phase(TaskPhase::Verifying);
+                        let check = check_on(&pool, &project_info, block, r.clone()).await;
+                        //only a verifier's result counts as a verification, not the shape check
+                        match &check {
+                            ProofCheck::Verified => state.metrics.verification(&project_name, true),
+                            ProofCheck::Rejected(_) => state.metrics.verification(&project_name, false),
+                            _ => {},
+                        }
+                        (status, reason) = check_status(&check);
+                    }
+                    match &reason {
+                        Some(reason) => {
+                            //a wrong proof would only be rejected on chain, tell the pool why instead
+                            error!("{} proof of block {} is not submitted: {}",project_name,block,reason);
+                            state.metrics.task(&project_name, TaskEvent::Invalid);
+                            ProofOutput { k: r.k, ..Default::default() }
+                        },
+                        None => {
+                            state.metrics.task(&project_name, TaskEvent::Completed);
+                            state.metrics.proof_duration(&project_name, proved_in);
+                            r
+                        },
+                    }
+                },
+                Err(e) => {
+                    error!("{} proof of block {} failed: {}",project_name.clone(),block,e);
+                    state.metrics.task(&project_name, TaskEvent::Failed);
+                    status=PROOF_STATUS_FAILED;
+                    ProofOutput::default()
+                },
+            };
+            let time_gap =(Instant::now().duration_since(time_started).as_millis() as u32)/1000;
+            info!("try to sumbit the block {} proof to zkpool,{} instances,{} proof bytes,time consumed:{}",block,agg_proof_result.instances.len(),agg_proof_result.proof.len(),time_gap);
+
+            permit.set_phase(TaskPhase::Submitting);
+            let record = SubmitRecord {
+                project_name: project_name.clone(),
+                task_id: block.to_string(),
+                proof: backend.serialize(&agg_proof_result),
+                degree: agg_proof_result.k,
+                time: time_gap,
+                status,
+                reason,
+            };
+            //keep the proof until the pool acknowledges it
+            if let Err(error) = client.outbox.store(&record) {
+                error!("Failed to store the proof of block {} in outbox: {}", block, error);
+            }
+            if let Err(error) = client.sender().send(record.to_message(0)).await { 
+                error!("Failed to send PoolResponse: {}", error);
+            }else{
+                info!("zkpool:send the proof of block:{} success,time consumed:{}",block,time_gap);
+            }
+            info!("zkpool:end computed the task of block:{}",block);
+        });
+
+        info!("******one block task in process********");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,246 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use crate::{config::ProverConfig, prover::{ProjectInfo, ProverState}};
+
+/// Re-read the project config and swap the projects of `state` in one go, returns what changed.
+/// Tasks in flight keep the `ProjectInfo` they cloned when they started.
+pub async fn reload_projects(state: &ProverState, config: Option<&PathBuf>) -> anyhow::Result<Vec<String>> {
+    let projects = ProverConfig::load(config.map(|path| path.as_path()))?.projects()?;
+    let projects: HashMap<String, ProjectInfo> = projects
+        .into_iter()
+        .map(|project| (project.name.clone(), project))
+        .collect();
+    let mut project_map = state.projects.lock().await;
+    let changes = diff(&project_map, &projects);
+    *project_map = projects;
+    Ok(changes)
+}
+
+/// One line per added, removed or updated project, rpc urls are not logged as they may hold api keys.
+fn diff(old: &HashMap<String, ProjectInfo>, new: &HashMap<String, ProjectInfo>) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (name, project) in new {
+        match old.get(name) {
+            None => changes.push(format!("+ {} (backend {})", name, project.backend.name())),
+            Some(previous) => {
+                if previous.rpc_url != project.rpc_url {
+                    changes.push(format!("~ {} rpc url changed", name));
+                }
+                if previous.cost != project.cost {
+                    changes.push(format!(
+                        "~ {} cost {} cpu {} GiB -> {} cpu {} GiB",
+                        name, previous.cost.cpu, previous.cost.memory_gb, project.cost.cpu, project.cost.memory_gb
+                    ));
+                }
+                if previous.verifier != project.verifier {
+                    changes.push(format!("~ {} verifier contract changed", name));
+                }
+                if previous.verify != project.verify {
+                    changes.push(format!("~ {} verify {} -> {}", name, previous.verify, project.verify));
+                }
+                if previous.backend.name() != project.backend.name() {
+                    changes.push(format!(
+                        "~ {} backend {} -> {}",
+                        name,
+                        previous.backend.name(),
+                        project.backend.name()
+                    ));
+                }
+            }
+        }
+    }
+    for name in old.keys() {
+        if !new.contains_key(name) {
+            changes.push(format!("- {}", name));
+        }
+    }
+    changes.sort();
+    changes
+}
+
+#[cfg(unix)]
+mod unix {
+    use std::{
+        fs::{self, DirBuilder, Permissions},
+        io::{self, ErrorKind},
+        os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt},
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use futures_util::SinkExt;
+    use tokio::{
+        net::{UnixListener, UnixStream},
+        signal::unix::{signal, SignalKind},
+    };
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::{Framed, LinesCodec};
+    use tracing::{error, info, warn};
+
+    use super::reload_projects;
+    use crate::{logging::LogHandle, prover::ProverState};
+
+    async fn reload(state: &ProverState, config: Option<&PathBuf>, trigger: &str) -> Result<String, String> {
+        match reload_projects(state, config).await {
+            Ok(changes) => {
+                if changes.is_empty() {
+                    info!("Reloaded projects on {}, nothing changed", trigger);
+                } else {
+                    info!("Reloaded projects on {}:\n{}", trigger, changes.join("\n"));
+                }
+                Ok(format!("ok: {} change(s)", changes.len()))
+            }
+            Err(e) => {
+                //keep serving the current projects
+                error!("Failed to reload projects on {}: {:#}", trigger, e);
+                Err(format!("error: {:#}", e).replace('\n', "; "))
+            }
+        }
+    }
+
+    /// Reload the projects on SIGHUP.
+    pub fn watch_sighup(state: Arc<ProverState>, config: Option<PathBuf>) -> std::io::Result<()> {
+        let mut hangup = signal(SignalKind::hangup())?;
+        tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                let _ = reload(&state, config.as_ref(), "SIGHUP").await;
+            }
+        });
+        Ok(())
+    }
+
+    /// Serve line based control commands on a unix socket, `reload` does the same as SIGHUP
+    /// and `log <filter>` changes the log filter.
+    pub fn serve_control(
+        state: Arc<ProverState>,
+        config: Option<PathBuf>,
+        path: PathBuf,
+        log: LogHandle,
+    ) -> std::io::Result<()> {
+        //a socket left by a previous run would make bind fail, anything else at the path is not ours to delete
+        match fs::symlink_metadata(&path) {
+            Ok(metadata) if metadata.file_type().is_socket() => fs::remove_file(&path)?,
+            Ok(_) => {
+                return Err(io::Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{} exists and is not a socket", path.display()),
+                ))
+            }
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+        let listener = bind_private(&path)?;
+        info!("Listening for control commands on {}", path.display());
+        tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let state = state.clone();
+                        let config = config.clone();
+                        let log = log.clone();
+                        tokio::spawn(async move { handle_control(stream, state, config, log).await });
+                    }
+                    Err(e) => {
+                        warn!("Failed to accept control connection: {}", e);
+                    }
+                }
+            }
+        });
+        Ok(())
+    }
+
+    /// Bind a socket only its owner can connect to: anyone who can connect may reload the config
+    /// or change the log filter. It is bound in a private directory until its permissions are set,
+    /// so that no one connects in between.
+    pub(super) fn bind_private(path: &Path) -> io::Result<UnixListener> {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let dir = path.with_file_name(format!(".{}.{}", name, std::process::id()));
+        DirBuilder::new().mode(0o700).create(&dir)?;
+        let bound = dir.join("sock");
+        let result = UnixListener::bind(&bound).and_then(|listener| {
+            fs::set_permissions(&bound, Permissions::from_mode(0o600))?;
+            fs::rename(&bound, path)?;
+            Ok(listener)
+        });
+        let _ = fs::remove_file(&bound);
+        let _ = fs::remove_dir(&dir);
+        result
+    }
+
+    async fn handle_control(stream: UnixStream, state: Arc<ProverState>, config: Option<PathBuf>, log: LogHandle) {
+        let mut framed = Framed::new(stream, LinesCodec::new_with_max_length(1024));
+        while let Some(Ok(line)) = framed.next().await {
+            let reply = match line.trim() {
+                "reload" => match reload(&state, config.as_ref(), "control command").await {
+                    Ok(r) | Err(r) => r,
+                },
+                command if command.starts_with("log ") => {
+                    let filter = command["log ".len()..].trim();
+                    match log.set_filter(filter) {
+                        Ok(()) => {
+                            info!("Log filter changed to {}", filter);
+                            String::from("ok")
+                        }
+                        Err(e) => format!("error: {}", e),
+                    }
+                }
+                "" => continue,
+                command => format!("error: unknown command {}", command),
+            };
+            if framed.send(reply).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(unix)]
+pub use unix::{serve_control, watch_sighup};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend, scheduler::TaskCost};
+
+    fn project(name: &str, rpc_url: &str) -> (String, ProjectInfo) {
+        (
+            name.to_string(),
+            ProjectInfo {
+                name: name.to_string(),
+                rpc_url: rpc_url.to_string(),
+                backend: backend::lookup("taikoA5").unwrap(),
+                cost: TaskCost { cpu: 8, memory_gb: 32 },
+                verify: false,
+                verifier: None,
+            },
+        )
+    }
+
+    #[test]
+    fn diff_projects() {
+        let old: HashMap<_, _> = vec![project("a", "http://a"), project("b", "http://b"), project("c", "http://c")]
+            .into_iter()
+            .collect();
+        let new: HashMap<_, _> = vec![project("a", "http://a"), project("b", "http://b2"), project("d", "http://d")]
+            .into_iter()
+            .collect();
+        assert_eq!(diff(&old, &new), vec!["+ d (backend taikoA5)", "- c", "~ b rpc url changed"]);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn control_socket_is_private() {
+        use std::{fs, os::unix::fs::PermissionsExt};
+
+        let dir = std::env::temp_dir().join(format!("zkpool-control-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+        let _ = fs::remove_file(&path);
+        let _listener = unix::bind_private(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        //nothing is left of the private directory
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+        tokio::net::UnixStream::connect(&path).await.unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
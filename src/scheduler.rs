@@ -0,0 +1,331 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt,
+    sync::Mutex,
+    time::Instant,
+};
+
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+/// Resources one task of a project holds while it is proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskCost {
+    pub cpu: u32,
+    pub memory_gb: u32,
+}
+
+/// What the prover may run at once, a task larger than the whole capacity still runs alone.
+#[derive(Debug, Clone, Copy)]
+pub struct Capacity {
+    pub slots: usize,
+    pub cpu: u32,
+    /// no memory limit if not set
+    pub memory_gb: Option<u32>,
+}
+
+impl Default for Capacity {
+    fn default() -> Self {
+        Self { slots: 1, cpu: u32::MAX, memory_gb: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskPhase {
+    Queued,
+    Proving,
+    Verifying,
+    Submitting,
+}
+
+impl fmt::Display for TaskPhase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TaskPhase::Queued => write!(f, "queued"),
+            TaskPhase::Proving => write!(f, "proving"),
+            TaskPhase::Verifying => write!(f, "verifying"),
+            TaskPhase::Submitting => write!(f, "submitting"),
+        }
+    }
+}
+
+/// One task received from the pool.
+#[derive(Debug, Clone)]
+pub struct TaskState {
+    pub project_name: String,
+    pub task_id: u64,
+    pub cost: TaskCost,
+    pub phase: TaskPhase,
+    /// when the task entered its current phase
+    pub since: Instant,
+    /// order in which the tasks were received
+    pub arrival: u64,
+    /// asks the task to stop, the backend checks it between proving phases
+    pub cancel: CancellationToken,
+}
+
+type TaskKey = (String, u64);
+
+struct Inner {
+    capacity: Capacity,
+    tasks: HashMap<TaskKey, TaskState>,
+    //queued tasks, admitted in order
+    queue: VecDeque<TaskKey>,
+    arrivals: u64,
+    running: usize,
+    used_cpu: u32,
+    used_memory_gb: u32,
+}
+
+impl Inner {
+    fn fits(&self, cost: TaskCost) -> bool {
+        if self.running >= self.capacity.slots {
+            return false;
+        }
+        if self.running == 0 {
+            return true;
+        }
+        let memory_fits = self
+            .capacity
+            .memory_gb
+            .map_or(true, |memory_gb| self.used_memory_gb.saturating_add(cost.memory_gb) <= memory_gb);
+        self.used_cpu.saturating_add(cost.cpu) <= self.capacity.cpu && memory_fits
+    }
+}
+
+/// Admits tasks to the proving slots according to their cost.
+pub struct Scheduler {
+    inner: Mutex<Inner>,
+    notify: Notify,
+}
+
+impl Scheduler {
+    pub fn new(capacity: Capacity) -> Self {
+        Self {
+            inner: Mutex::new(Inner {
+                capacity,
+                tasks: HashMap::new(),
+                queue: VecDeque::new(),
+                arrivals: 0,
+                running: 0,
+                used_cpu: 0,
+                used_memory_gb: 0,
+            }),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn set_capacity(&self, capacity: Capacity) {
+        self.inner.lock().unwrap().capacity = capacity;
+        self.notify.notify_waiters();
+    }
+
+    /// Queue a task and return its cancellation token, None if it is already tracked.
+    /// A project keeps at most `slots` tasks, a new task supersedes the ones received first:
+    /// those are cancelled, whatever their ids, as the pool only sends the tasks it wants proven now.
+    pub fn enqueue(&self, project_name: &str, task_id: u64, cost: TaskCost) -> Option<CancellationToken> {
+        let key = (project_name.to_string(), task_id);
+        let mut inner = self.inner.lock().unwrap();
+        if inner.tasks.contains_key(&key) {
+            return None;
+        }
+        //cancelled tasks are on their way out
+        let mut tasks: Vec<(u64, u64)> = inner
+            .tasks
+            .values()
+            .filter(|task| task.project_name == project_name && !task.cancel.is_cancelled())
+            .map(|task| (task.arrival, task.task_id))
+            .collect();
+        tasks.sort_unstable();
+        let superseded = (tasks.len() + 1).saturating_sub(inner.capacity.slots.max(1));
+        for (_, id) in tasks.into_iter().take(superseded) {
+            let old = (project_name.to_string(), id);
+            if let Some(task) = inner.tasks.get(&old) {
+                task.cancel.cancel();
+            }
+            //a queued task is dropped now, a running one keeps its resources until its proof has stopped
+            if let Some(position) = inner.queue.iter().position(|queued| *queued == old) {
+                inner.queue.remove(position);
+                inner.tasks.remove(&old);
+            }
+        }
+        if superseded > 0 {
+            self.notify.notify_waiters();
+        }
+        let cancel = CancellationToken::new();
+        inner.arrivals += 1;
+        let arrival = inner.arrivals;
+        inner.tasks.insert(
+            key.clone(),
+            TaskState {
+                project_name: project_name.to_string(),
+                task_id,
+                cost,
+                phase: TaskPhase::Queued,
+                since: Instant::now(),
+                arrival,
+                cancel: cancel.clone(),
+            },
+        );
+        inner.queue.push_back(key);
+        Some(cancel)
+    }
+
+    /// Wait for the task's turn, None if it was cancelled while queued.
+    /// The task keeps its resources until the permit is dropped.
+    pub async fn admit(&self, project_name: &str, task_id: u64) -> Option<TaskPermit<'_>> {
+        let key = (project_name.to_string(), task_id);
+        loop {
+            //register before checking so that a release in between is not missed
+            let notified = self.notify.notified();
+            {
+                let mut inner = self.inner.lock().unwrap();
+                let cost = inner.tasks.get(&key)?.cost;
+                if inner.queue.front() == Some(&key) && inner.fits(cost) {
+                    inner.queue.pop_front();
+                    inner.running += 1;
+                    inner.used_cpu = inner.used_cpu.saturating_add(cost.cpu);
+                    inner.used_memory_gb = inner.used_memory_gb.saturating_add(cost.memory_gb);
+                    if let Some(task) = inner.tasks.get_mut(&key) {
+                        task.phase = TaskPhase::Proving;
+                        task.since = Instant::now();
+                    }
+                    return Some(TaskPermit { scheduler: self, key, cost });
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Every tracked task, oldest first.
+    pub fn active(&self) -> Vec<TaskState> {
+        let inner = self.inner.lock().unwrap();
+        let mut tasks: Vec<TaskState> = inner.tasks.values().cloned().collect();
+        tasks.sort_by_key(|task| task.since);
+        tasks
+    }
+
+    /// Cancel every task, running tasks keep their resources until their proof has stopped.
+    pub fn cancel_all(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        for task in inner.tasks.values() {
+            task.cancel.cancel();
+        }
+        let queue: Vec<TaskKey> = inner.queue.drain(..).collect();
+        for key in queue {
+            inner.tasks.remove(&key);
+        }
+        drop(inner);
+        self.notify.notify_waiters();
+    }
+}
+
+/// A task admitted to a proving slot.
+pub struct TaskPermit<'a> {
+    scheduler: &'a Scheduler,
+    key: TaskKey,
+    cost: TaskCost,
+}
+
+impl TaskPermit<'_> {
+    pub fn set_phase(&self, phase: TaskPhase) {
+        let mut inner = self.scheduler.inner.lock().unwrap();
+        if let Some(task) = inner.tasks.get_mut(&self.key) {
+            task.phase = phase;
+            task.since = Instant::now();
+        }
+    }
+}
+
+impl Drop for TaskPermit<'_> {
+    fn drop(&mut self) {
+        {
+            let mut inner = self.scheduler.inner.lock().unwrap();
+            inner.tasks.remove(&self.key);
+            inner.running -= 1;
+            inner.used_cpu = inner.used_cpu.saturating_sub(self.cost.cpu);
+            inner.used_memory_gb = inner.used_memory_gb.saturating_sub(self.cost.memory_gb);
+        }
+        self.scheduler.notify.notify_waiters();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    const COST: TaskCost = TaskCost { cpu: 8, memory_gb: 32 };
+
+    #[tokio::test]
+    async fn admit_by_cost() {
+        let scheduler = Scheduler::new(Capacity { slots: 3, cpu: 16, memory_gb: Some(64) });
+        for task_id in 1..=3 {
+            assert!(scheduler.enqueue("taikoA5", task_id, COST).is_some());
+        }
+        assert!(scheduler.enqueue("taikoA5", 1, COST).is_none());
+
+        let first = scheduler.admit("taikoA5", 1).await.unwrap();
+        let _second = scheduler.admit("taikoA5", 2).await.unwrap();
+        //a third task would need 24 cpus
+        assert!(timeout(Duration::from_millis(20), scheduler.admit("taikoA5", 3)).await.is_err());
+
+        let phases: Vec<TaskPhase> = scheduler.active().iter().map(|task| task.phase).collect();
+        assert_eq!(phases, vec![TaskPhase::Queued, TaskPhase::Proving, TaskPhase::Proving]);
+
+        drop(first);
+        let third = timeout(Duration::from_millis(20), scheduler.admit("taikoA5", 3)).await.unwrap();
+        assert!(third.is_some());
+        assert_eq!(scheduler.active().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn cancel_all_tasks() {
+        let scheduler = Arc::new(Scheduler::new(Capacity { slots: 2, cpu: 8, memory_gb: None }));
+        let cancel = scheduler.enqueue("taikoA5", 1, COST).unwrap();
+        scheduler.enqueue("taikoA5", 2, COST);
+        let running = {
+            let scheduler = scheduler.clone();
+            tokio::spawn(async move {
+                let _permit = scheduler.admit("taikoA5", 1).await.unwrap();
+                cancel.cancelled().await;
+            })
+        };
+        tokio::task::yield_now().await;
+
+        scheduler.cancel_all();
+        assert!(scheduler.admit("taikoA5", 2).await.is_none());
+        //the running task is tracked until it stops
+        running.await.unwrap();
+        assert!(scheduler.active().is_empty());
+    }
+
+    #[tokio::test]
+    async fn newer_task_supersedes_older() {
+        let scheduler = Scheduler::new(Capacity::default());
+        let first = scheduler.enqueue("taikoA5", 10, COST).unwrap();
+        let permit = scheduler.admit("taikoA5", 10).await.unwrap();
+
+        //the running task is cancelled and the newer one waits for its slot
+        let second = scheduler.enqueue("taikoA5", 11, COST).unwrap();
+        assert!(first.is_cancelled());
+        let third = scheduler.enqueue("taikoA5", 12, COST).unwrap();
+        assert!(second.is_cancelled());
+        assert!(scheduler.admit("taikoA5", 11).await.is_none());
+        //the last task received wins even with a lower id, other projects are left alone
+        let fourth = scheduler.enqueue("taikoA5", 9, COST).unwrap();
+        assert!(third.is_cancelled());
+        let other = scheduler.enqueue("taikoB", 1, COST).unwrap();
+        assert!(!fourth.is_cancelled() && !other.is_cancelled());
+
+        drop(permit);
+        let next = timeout(Duration::from_millis(20), scheduler.admit("taikoA5", 9)).await.unwrap();
+        assert!(next.is_some());
+        let mut tasks: Vec<u64> = scheduler.active().iter().map(|task| task.task_id).collect();
+        tasks.sort_unstable();
+        assert_eq!(tasks, vec![1, 9]);
+    }
+}
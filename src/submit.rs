@@ -0,0 +1,311 @@
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use json_rpc_types::Id;
+use serde::{Deserialize, Serialize};
+use taiko_stratum::{
+    message::StratumMessage,
+    proof::{self, ProofFormat},
+};
+use tracing::{error, warn};
+
+use crate::prover::{PROOF_STATUS_FAILED, PROOF_STATUS_INVALID};
+
+/// Sends of one proof before giving up on the pool's response
+pub const SUBMIT_MAX_ATTEMPTS: u32 = 5;
+
+/// How submits are sent on a connection, from the capabilities agreed with the pool.
+#[derive(Debug, Clone, Copy)]
+pub struct SubmitFormat {
+    pub proof: ProofFormat,
+    /// the pool takes status 2 and the reason of a proof failing the prover's checks
+    pub invalid_proof: bool,
+}
+
+/// Everything needed to (re)send one `zkpool.submit`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SubmitRecord {
+    pub project_name: String,
+    pub task_id: String,
+    pub proof: String,
+    pub degree: u8,
+    pub time: u32,
+    pub status: u8,
+    /// why the proof is not submitted as computed, records from before it have none
+    #[serde(default)]
+    pub reason: Option<String>,
+}
+
+impl SubmitRecord {
+    /// Take the submit out of a message, giving back any other message untouched.
+    #[allow(clippy::result_large_err)]
+    pub fn from_message(message: StratumMessage) -> Result<Self, StratumMessage> {
+        match message {
+            StratumMessage::Submit(_, project_name, task_id, proof, degree, time, status, reason) => Ok(Self {
+                project_name,
+                task_id,
+                proof,
+                degree,
+                time,
+                status,
+                reason,
+            }),
+            message => Err(message),
+        }
+    }
+
+    /// Whether both records submit the same task.
+    pub fn same_task(&self, other: &SubmitRecord) -> bool {
+        self.project_name == other.project_name && self.task_id == other.task_id
+    }
+
+    pub fn to_message(&self, id: u64) -> StratumMessage {
+        self.to_message_as(
+            id,
+            SubmitFormat {
+                proof: ProofFormat::V1,
+                invalid_proof: true,
+            },
+        )
+    }
+
+    /// The submit in `format`, records from before the versioned format hold legacy proofs.
+    pub fn to_message_as(&self, id: u64, format: SubmitFormat) -> StratumMessage {
+        let proof = match proof::convert(&self.proof, format.proof) {
+            Ok(r) => r,
+            Err(e) => {
+                warn!("Sending the {} proof of task {} as stored: {}", self.project_name, self.task_id, e);
+                self.proof.clone()
+            }
+        };
+        let (status, reason) = match format.invalid_proof {
+            true => (self.status, self.reason.clone()),
+            //other pools know the ok and failed statuses and six params only
+            false if self.status == PROOF_STATUS_INVALID => (PROOF_STATUS_FAILED, None),
+            false => (self.status, None),
+        };
+        StratumMessage::Submit(
+            Id::Num(id),
+            self.project_name.clone(),
+            self.task_id.clone(),
+            proof,
+            self.degree,
+            self.time,
+            status,
+            reason,
+        )
+    }
+}
+
+struct PendingSubmit {
+    record: SubmitRecord,
+    sent_at: Instant,
+    attempts: u32,
+}
+
+/// Submits waiting for the pool's response, keyed by request id.
+pub struct PendingSubmits {
+    pending: HashMap<u64, PendingSubmit>,
+    //ids of earlier sends of submits being resent, the pool may still answer them
+    retired: HashMap<u64, SubmitRecord>,
+    //submits given up, not yet deleted from the outbox
+    given_up: Vec<SubmitRecord>,
+    timeout: Duration,
+    max_attempts: u32,
+}
+
+impl PendingSubmits {
+    pub fn new(timeout: Duration, max_attempts: u32) -> Self {
+        Self {
+            pending: HashMap::new(),
+            retired: HashMap::new(),
+            given_up: Vec::new(),
+            timeout,
+            max_attempts: max_attempts.max(1),
+        }
+    }
+
+    /// Track a submit sent with `id`, `attempts` counts this send.
+    pub fn sent(&mut self, id: u64, record: SubmitRecord, attempts: u32) {
+        self.pending.insert(
+            id,
+            PendingSubmit {
+                record,
+                sent_at: Instant::now(),
+                attempts,
+            },
+        );
+    }
+
+    /// The pool answered request `id`, returns the submit if it was one.
+    /// An answer to an earlier send of a submit acknowledges it too, it is not sent again.
+    pub fn ack(&mut self, id: u64) -> Option<SubmitRecord> {
+        let record = match self.pending.remove(&id) {
+            Some(pending) => pending.record,
+            None => self.retired.remove(&id)?,
+        };
+        self.pending.retain(|_, pending| !pending.record.same_task(&record));
+        self.forget(&record);
+        Some(record)
+    }
+
+    /// Submits without a response in time, to be sent again with their attempt count so far.
+    /// Submits out of attempts are dropped, see `given_up`.
+    pub fn expired(&mut self) -> Vec<(SubmitRecord, u32)> {
+        let timeout = self.timeout;
+        let ids: Vec<u64> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.sent_at.elapsed() >= timeout)
+            .map(|(id, _)| *id)
+            .collect();
+        self.take(ids)
+    }
+
+    /// Every submit still waiting, to be sent again on a new connection.
+    pub fn drain(&mut self) -> Vec<(SubmitRecord, u32)> {
+        //the old connection won't answer anymore
+        self.retired.clear();
+        let ids: Vec<u64> = self.pending.keys().copied().collect();
+        self.take(ids)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Drop a submit that can't be sent, see `given_up`.
+    pub fn give_up(&mut self, record: SubmitRecord) {
+        self.forget(&record);
+        self.given_up.push(record);
+    }
+
+    /// Submits dropped since the last call.
+    pub fn given_up(&mut self) -> Vec<SubmitRecord> {
+        std::mem::take(&mut self.given_up)
+    }
+
+    fn take(&mut self, mut ids: Vec<u64>) -> Vec<(SubmitRecord, u32)> {
+        //resend in the original order
+        ids.sort_unstable();
+        let mut resend = Vec::new();
+        for id in ids {
+            if let Some(pending) = self.pending.remove(&id) {
+                if pending.attempts >= self.max_attempts {
+                    error!(
+                        "give up the {} proof of task {} after {} attempts without response",
+                        pending.record.project_name, pending.record.task_id, pending.attempts
+                    );
+                    self.give_up(pending.record);
+                    continue;
+                }
+                self.retired.insert(id, pending.record.clone());
+                resend.push((pending.record, pending.attempts));
+            }
+        }
+        resend
+    }
+
+    fn forget(&mut self, record: &SubmitRecord) {
+        self.retired.retain(|_, retired| !retired.same_task(record));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(task_id: u64) -> SubmitRecord {
+        SubmitRecord {
+            project_name: "taikoA5".to_string(),
+            task_id: task_id.to_string(),
+            proof: "#1#0x00".to_string(),
+            degree: 20,
+            time: 60,
+            status: 1,
+            reason: None,
+        }
+    }
+
+    #[test]
+    fn ack_by_id() {
+        let mut pending = PendingSubmits::new(Duration::from_secs(60), SUBMIT_MAX_ATTEMPTS);
+        pending.sent(3, record(100), 1);
+        pending.sent(4, record(101), 1);
+
+        //a response to another request is not a submit
+        assert!(pending.ack(2).is_none());
+        assert_eq!(pending.ack(4).unwrap().task_id, "101");
+        assert!(pending.ack(4).is_none());
+        assert!(!pending.is_empty());
+        assert_eq!(pending.ack(3).unwrap().task_id, "100");
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn late_ack_of_earlier_send() {
+        let mut pending = PendingSubmits::new(Duration::ZERO, SUBMIT_MAX_ATTEMPTS);
+        pending.sent(1, record(100), 1);
+        let (resend, attempts) = pending.expired().pop().unwrap();
+        pending.sent(2, resend, attempts + 1);
+
+        //the pool answers the first send while the second is waiting
+        assert_eq!(pending.ack(1).unwrap().task_id, "100");
+        assert!(pending.is_empty());
+        assert!(pending.ack(2).is_none());
+    }
+
+    #[test]
+    fn resend_expired_in_order() {
+        let mut pending = PendingSubmits::new(Duration::from_millis(20), SUBMIT_MAX_ATTEMPTS);
+        pending.sent(7, record(102), 2);
+        pending.sent(5, record(100), 1);
+        assert!(pending.expired().is_empty());
+
+        std::thread::sleep(Duration::from_millis(30));
+        pending.sent(6, record(101), 1);
+        let resend: Vec<(String, u32)> = pending.expired().into_iter().map(|(r, attempts)| (r.task_id, attempts)).collect();
+        assert_eq!(resend, vec![("100".to_string(), 1), ("102".to_string(), 2)]);
+
+        //a new connection sends everything still waiting
+        let resend: Vec<String> = pending.drain().into_iter().map(|(r, _)| r.task_id).collect();
+        assert_eq!(resend, vec!["101"]);
+        assert!(pending.is_empty());
+    }
+
+    #[test]
+    fn give_up_after_max_attempts() {
+        let mut pending = PendingSubmits::new(Duration::ZERO, 3);
+        pending.sent(1, record(100), 3);
+        pending.sent(2, record(101), 2);
+
+        let resend: Vec<(String, u32)> = pending.expired().into_iter().map(|(r, attempts)| (r.task_id, attempts)).collect();
+        assert_eq!(resend, vec![("101".to_string(), 2)]);
+        assert!(pending.is_empty());
+        let given_up: Vec<String> = pending.given_up().into_iter().map(|r| r.task_id).collect();
+        assert_eq!(given_up, vec!["100"]);
+        assert!(pending.given_up().is_empty());
+    }
+
+    #[test]
+    fn invalid_proof_needs_capability() {
+        let mut invalid = record(100);
+        invalid.proof = String::new();
+        invalid.status = PROOF_STATUS_INVALID;
+        invalid.reason = Some("verification failed".to_string());
+        let status_of = |invalid_proof| {
+            let format = SubmitFormat {
+                proof: ProofFormat::V1,
+                invalid_proof,
+            };
+            match invalid.to_message_as(1, format) {
+                StratumMessage::Submit(_, _, _, _, _, _, status, reason) => (status, reason),
+                _ => unreachable!(),
+            }
+        };
+        assert_eq!(status_of(true), (PROOF_STATUS_INVALID, Some("verification failed".to_string())));
+        assert_eq!(status_of(false), (PROOF_STATUS_FAILED, None));
+    }
+}
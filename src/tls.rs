@@ -0,0 +1,261 @@
+use std::{
+    fs::File,
+    io::{self, BufReader},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::SystemTime,
+};
+
+use anyhow::{anyhow, bail, Context};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use rustls::{
+    client::{ServerCertVerified, ServerCertVerifier, WebPkiVerifier},
+    Certificate, ClientConfig, OwnedTrustAnchor, PrivateKey, RootCertStore, ServerName,
+};
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::{client::TlsStream, TlsConnector};
+
+/// TLS options shared by every `tls://` pool endpoint.
+#[derive(Debug, Clone, Default)]
+pub struct TlsSettings {
+    /// PEM CA bundle to trust instead of the built-in web roots
+    pub ca_file: Option<PathBuf>,
+    /// base64 sha256 of the pool SubjectPublicKeyInfo, the certificate must match one of them
+    pub spki_pins: Vec<String>,
+    /// PEM certificate chain and private key to authenticate the device
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+}
+
+/// Build the connector once at startup so that bad certificate files fail fast.
+pub fn connector(settings: &TlsSettings) -> anyhow::Result<TlsConnector> {
+    let mut roots = RootCertStore::empty();
+    match &settings.ca_file {
+        Some(path) => {
+            for cert in read_certs(path)? {
+                roots
+                    .add(&cert)
+                    .with_context(|| format!("invalid CA certificate in {}", path.display()))?;
+            }
+        }
+        None => roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(ta.subject, ta.spki, ta.name_constraints)
+        })),
+    }
+
+    let mut pins = Vec::new();
+    for pin in &settings.spki_pins {
+        let digest = STANDARD.decode(pin).with_context(|| format!("invalid SPKI pin {}", pin))?;
+        if digest.len() != 32 {
+            bail!("SPKI pin {} is not a base64 sha256 digest", pin);
+        }
+        pins.push(digest);
+    }
+
+    let builder = ClientConfig::builder().with_safe_defaults().with_custom_certificate_verifier(Arc::new(
+        PinnedVerifier { inner: WebPkiVerifier::new(roots, None), pins },
+    ));
+    let config = match (&settings.client_cert, &settings.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+            .context("invalid client certificate")?,
+        (None, None) => builder.with_no_client_auth(),
+        _ => bail!("client certificate and key must be set together"),
+    };
+    Ok(TlsConnector::from(Arc::new(config)))
+}
+
+/// Run the TLS handshake over a connected socket, the server name is the host part of `address`.
+pub async fn connect(connector: &TlsConnector, address: &str, socket: TcpStream) -> io::Result<TlsStream<TcpStream>> {
+    let host = match address.rsplit_once(':') {
+        Some((host, _)) => host.trim_start_matches('[').trim_end_matches(']'),
+        None => address,
+    };
+    let server_name = ServerName::try_from(host)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+    connector.connect(server_name, socket).await
+}
+
+struct PinnedVerifier {
+    inner: WebPkiVerifier,
+    pins: Vec<Vec<u8>>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        intermediates: &[Certificate],
+        server_name: &ServerName,
+        scts: &mut dyn Iterator<Item = &[u8]>,
+        ocsp_response: &[u8],
+        now: SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, scts, ocsp_response, now)?;
+        if self.pins.is_empty() {
+            return Ok(verified);
+        }
+        let spki = subject_public_key_info(&end_entity.0)
+            .ok_or_else(|| rustls::Error::General("malformed server certificate".into()))?;
+        let digest = Sha256::digest(spki);
+        if self.pins.iter().any(|pin| pin.as_slice() == digest.as_slice()) {
+            Ok(verified)
+        } else {
+            Err(rustls::Error::General(format!(
+                "server public key {} does not match any pin",
+                STANDARD.encode(digest)
+            )))
+        }
+    }
+}
+
+/// One DER element: (tag, whole element, content, rest of the input).
+type DerElement<'a> = (u8, &'a [u8], &'a [u8], &'a [u8]);
+
+fn der_next(input: &[u8]) -> Option<DerElement<'_>> {
+    let tag = *input.first()?;
+    let first = *input.get(1)? as usize;
+    let (len, header) = if first < 0x80 {
+        (first, 2)
+    } else {
+        let num = first & 0x7f;
+        if num == 0 || num > 4 {
+            return None;
+        }
+        let mut len = 0usize;
+        for byte in input.get(2..2 + num)? {
+            len = (len << 8) | *byte as usize;
+        }
+        (len, 2 + num)
+    };
+    let end = header.checked_add(len)?;
+    Some((tag, input.get(..end)?, input.get(header..end)?, &input[end..]))
+}
+
+/// The DER SubjectPublicKeyInfo of an X.509 certificate.
+fn subject_public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    let (_, _, certificate, _) = der_next(cert)?;
+    let (_, _, tbs, _) = der_next(certificate)?;
+    let mut rest = tbs;
+    //skip the explicit version if present
+    if rest.first() == Some(&0xa0) {
+        rest = der_next(rest)?.3;
+    }
+    //serial, signature algorithm, issuer, validity, subject
+    for _ in 0..5 {
+        rest = der_next(rest)?.3;
+    }
+    let (tag, spki, _, _) = der_next(rest)?;
+    if tag != 0x30 {
+        return None;
+    }
+    Some(spki)
+}
+
+fn read_certs(path: &Path) -> anyhow::Result<Vec<Certificate>> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .with_context(|| format!("read certificates from {}", path.display()))?;
+    if certs.is_empty() {
+        bail!("no certificate found in {}", path.display());
+    }
+    Ok(certs.into_iter().map(Certificate).collect())
+}
+
+fn read_key(path: &Path) -> anyhow::Result<PrivateKey> {
+    let file = File::open(path).with_context(|| format!("open {}", path.display()))?;
+    for item in rustls_pemfile::read_all(&mut BufReader::new(file))
+        .with_context(|| format!("read private key from {}", path.display()))?
+    {
+        match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => return Ok(PrivateKey(key)),
+            _ => {}
+        }
+    }
+    Err(anyhow!("no private key found in {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::SinkExt;
+    use json_rpc_types::Id;
+    use rustls::{server::AllowAnyAuthenticatedClient, ServerConfig};
+    use taiko_stratum::{codec::StratumCodec, message::StratumMessage};
+    use tokio::net::TcpListener;
+    use tokio_rustls::TlsAcceptor;
+    use tokio_stream::StreamExt;
+    use tokio_util::codec::Framed;
+
+    use super::*;
+
+    const SERVER_PIN: &str = "2zyg+p7FxedKwiILCw7ZtYI0Rz1EiXr5rRog+AeCAMM=";
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/tls").join(name)
+    }
+
+    /// Local pool that requires a client certificate and answers one request.
+    async fn serve_once() -> String {
+        let mut client_roots = RootCertStore::empty();
+        client_roots.add(&read_certs(&fixture("ca.pem")).unwrap()[0]).unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(Arc::new(AllowAnyAuthenticatedClient::new(client_roots)))
+            .with_single_cert(read_certs(&fixture("server.pem")).unwrap(), read_key(&fixture("server.key")).unwrap())
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("localhost:{}", listener.local_addr().unwrap().port());
+        tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            if let Ok(stream) = acceptor.accept(socket).await {
+                let mut framed = Framed::new(stream, StratumCodec::default());
+                if let Some(Ok(StratumMessage::Subscribe(id, ..))) = framed.next().await {
+                    let _ = framed.send(StratumMessage::Response(id, None, None)).await;
+                }
+            }
+        });
+        address
+    }
+
+    fn settings(pin: &str) -> TlsSettings {
+        TlsSettings {
+            ca_file: Some(fixture("ca.pem")),
+            spki_pins: vec![pin.to_string()],
+            client_cert: Some(fixture("client.pem")),
+            client_key: Some(fixture("client.key")),
+        }
+    }
+
+    #[tokio::test]
+    async fn mutual_tls_with_pinned_key() {
+        let address = serve_once().await;
+        let connector = connector(&settings(SERVER_PIN)).unwrap();
+        let socket = TcpStream::connect(address.replace("localhost", "127.0.0.1")).await.unwrap();
+        let stream = connect(&connector, &address, socket).await.unwrap();
+
+        let mut framed = Framed::new(stream, StratumCodec::default());
+        framed
+            .send(StratumMessage::Subscribe(Id::Num(7), "test".to_string(), "test".to_string(), 1, 0, 1, Vec::new()))
+            .await
+            .unwrap();
+        match framed.next().await {
+            Some(Ok(StratumMessage::Response(Id::Num(7), _, _))) => {}
+            _ => panic!("expect a response over tls"),
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_unpinned_key() {
+        let address = serve_once().await;
+        let other_pin = STANDARD.encode([0u8; 32]);
+        let connector = connector(&settings(&other_pin)).unwrap();
+        let socket = TcpStream::connect(address.replace("localhost", "127.0.0.1")).await.unwrap();
+        assert!(connect(&connector, &address, socket).await.is_err());
+    }
+}
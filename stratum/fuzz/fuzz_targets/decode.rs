@@ -0,0 +1,16 @@
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use taiko_stratum::codec::StratumCodec;
+use tokio_util::codec::{Decoder, Encoder};
+
+// bytes from a pool must never panic the decoder, and whatever decodes must encode again
+fuzz_target!(|data: &[u8]| {
+    let mut codec = StratumCodec::default();
+    let mut src = BytesMut::from(data);
+    while let Ok(Some(message)) = codec.decode(&mut src) {
+        let mut dst = BytesMut::new();
+        let _ = codec.encode(message, &mut dst);
+    }
+});
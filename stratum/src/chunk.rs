@@ -0,0 +1,244 @@
+// CHANGE(zkpool): split a zkpool.submit too large for one frame into zkpool.submit_chunk messages,
+// and join them again on the pool side
+
+use std::{collections::HashMap, io};
+
+use bytes::BytesMut;
+use json_rpc_types::Id;
+use sha2::{Digest, Sha256};
+use tokio_util::codec::Encoder;
+
+use crate::{codec::StratumCodec, message::StratumMessage};
+
+/// Most parts accepted for one submit
+pub const MAX_CHUNKS: u32 = 4096;
+
+/// Length of a message as a line of `StratumCodec`, without the delimiter.
+fn encoded_length(message: StratumMessage) -> io::Result<usize> {
+    let mut line = BytesMut::new();
+    StratumCodec::default().encode(message, &mut line)?;
+    Ok(line.len().saturating_sub(1))
+}
+
+/// Sha256 of a part, in hex.
+pub fn checksum(part: &str) -> String {
+    Sha256::digest(part.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Split a submit whose line is longer than `max_frame_length` into chunks, numbering their ids up from
+/// the submit's id. The response to the last chunk answers the whole submit.
+/// Other messages, submits that fit and submits with a reason are returned as they are.
+/// Fails if the fields of a chunk alone don't fit, or the proof needs more than `MAX_CHUNKS` chunks.
+pub fn split_submit(message: StratumMessage, max_frame_length: usize) -> io::Result<Vec<StratumMessage>> {
+    let (id, project_name, block, proof, degree, time, status) = match message {
+        StratumMessage::Submit(id, project_name, block, proof, degree, time, status, None) => {
+            (id, project_name, block, proof, degree, time, status)
+        }
+        message => return Ok(vec![message]),
+    };
+    let submit = |proof: String| {
+        StratumMessage::Submit(id.clone(), project_name.clone(), block.clone(), proof, degree, time, status, None)
+    };
+    if encoded_length(submit(proof.clone()))? <= max_frame_length {
+        return Ok(vec![submit(proof)]);
+    }
+    let chunk = |index: u32, total: u32, part: &str| {
+        let id = match &id {
+            Id::Num(id) => Id::Num(id + index as u64),
+            id => id.clone(),
+        };
+        StratumMessage::SubmitChunk(
+            id,
+            project_name.clone(),
+            block.clone(),
+            part.to_string(),
+            degree,
+            time,
+            status,
+            index,
+            total,
+            checksum(part),
+        )
+    };
+
+    //start from the room a chunk leaves for its part, at the widest index and total
+    let overhead = encoded_length(chunk(MAX_CHUNKS, MAX_CHUNKS, ""))?;
+    if overhead >= max_frame_length {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("a chunk takes {} bytes without its part, more than the frame length {}", overhead, max_frame_length),
+        ));
+    }
+    //escaping makes a part up to 6 times longer once encoded, shrink the parts until every chunk fits
+    let mut max_part = max_frame_length - overhead;
+    loop {
+        let parts = split_str(&proof, max_part);
+        if parts.len() > MAX_CHUNKS as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("the proof needs more than {} chunks of a frame length of {}", MAX_CHUNKS, max_frame_length),
+            ));
+        }
+        let total = parts.len() as u32;
+        let mut longest = 0;
+        for (index, part) in (0..).zip(&parts) {
+            longest = longest.max(encoded_length(chunk(index, total, part))?);
+        }
+        if longest <= max_frame_length {
+            return Ok((0..).zip(parts).map(|(index, part)| chunk(index, total, part)).collect());
+        }
+        //every byte of a part takes at least one byte encoded
+        max_part = max_part.saturating_sub(longest - max_frame_length).max(1);
+    }
+}
+
+/// Parts of at most `max` bytes, cut at char boundaries.
+fn split_str(s: &str, max: usize) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut rest = s;
+    while !rest.is_empty() {
+        let mut end = max.min(rest.len());
+        while !rest.is_char_boundary(end) {
+            end -= 1;
+        }
+        //a char longer than max still goes whole
+        if end == 0 {
+            end = rest.chars().next().map_or(rest.len(), char::len_utf8);
+        }
+        let (part, tail) = rest.split_at(end);
+        parts.push(part);
+        rest = tail;
+    }
+    parts
+}
+
+struct Partial {
+    total: u32,
+    parts: Vec<String>,
+}
+
+/// Joins the chunks of each submit, keyed by project and block.
+#[derive(Default)]
+pub struct ChunkAssembler {
+    partial: HashMap<(String, String), Partial>,
+}
+
+impl ChunkAssembler {
+    /// Take a decoded message. Chunks are held until the last part arrives, which returns the whole
+    /// submit with the last chunk's id; other messages are returned as they are.
+    /// A chunk out of order or with a bad checksum drops the parts received so far, index 0 starts over.
+    pub fn push(&mut self, message: StratumMessage) -> Result<Option<StratumMessage>, io::Error> {
+        let (id, project_name, block, part, degree, time, status, index, total, sum) = match message {
+            StratumMessage::SubmitChunk(id, project_name, block, part, degree, time, status, index, total, sum) => {
+                (id, project_name, block, part, degree, time, status, index, total, sum)
+            }
+            message => return Ok(Some(message)),
+        };
+        let key = (project_name, block);
+        if total == 0 || total > MAX_CHUNKS || index >= total {
+            self.partial.remove(&key);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid chunk index"));
+        }
+        if checksum(&part) != sum {
+            self.partial.remove(&key);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Chunk checksum mismatch"));
+        }
+        if index == 0 {
+            self.partial.insert(key.clone(), Partial { total, parts: Vec::new() });
+        }
+        let partial = match self.partial.get_mut(&key) {
+            Some(partial) if partial.total == total && partial.parts.len() == index as usize => partial,
+            _ => {
+                self.partial.remove(&key);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Chunk out of order"));
+            }
+        };
+        partial.parts.push(part);
+        if partial.parts.len() < total as usize {
+            return Ok(None);
+        }
+        let proof = self.partial.remove(&key).map(|partial| partial.parts.concat()).unwrap_or_default();
+        let (project_name, block) = key;
+        Ok(Some(StratumMessage::Submit(id, project_name, block, proof, degree, time, status, None)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+    use crate::codec::StratumCodec;
+
+    fn submit(proof: &str) -> StratumMessage {
+        StratumMessage::Submit(Id::Num(10), String::from("taikoA5"), String::from("42"), proof.to_string(), 1, 120, 0, None)
+    }
+
+    #[test]
+    fn split_and_join_through_the_codec() {
+        let proof = "ab#".repeat(3000);
+        let mut codec = StratumCodec::new(2048);
+        let chunks = split_submit(submit(&proof), codec.max_frame_length()).unwrap();
+        assert_eq!(chunks.len(), 5);
+
+        let mut wire = BytesMut::new();
+        for chunk in chunks {
+            codec.encode(chunk, &mut wire).unwrap();
+        }
+        let mut assembler = ChunkAssembler::default();
+        let mut joined = Vec::new();
+        while let Some(message) = codec.decode(&mut wire).unwrap() {
+            assert_eq!(message.name(), "zkpool.submit_chunk");
+            joined.extend(assembler.push(message).unwrap());
+        }
+        match joined.as_slice() {
+            [StratumMessage::Submit(Id::Num(14), project_name, block, joined_proof, 1, 120, 0, None)] => {
+                assert_eq!((project_name.as_str(), block.as_str()), ("taikoA5", "42"));
+                assert_eq!(joined_proof, &proof);
+            }
+            _ => panic!("expect one submit"),
+        }
+
+        //a submit that fits is not split
+        assert_eq!(split_submit(submit("ab"), 2048).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn chunks_fit_once_escaped() {
+        //every quote and control char is escaped, up to 6 bytes each
+        let proof = "\"\u{1}".repeat(2000);
+        let mut codec = StratumCodec::new(1024);
+        let chunks = split_submit(submit(&proof), codec.max_frame_length()).unwrap();
+        let mut assembler = ChunkAssembler::default();
+        let mut joined = Vec::new();
+        for chunk in chunks {
+            let mut line = BytesMut::new();
+            codec.encode(chunk, &mut line).unwrap();
+            assert!(line.len() - 1 <= codec.max_frame_length());
+            joined.extend(assembler.push(codec.decode(&mut line).unwrap().unwrap()).unwrap());
+        }
+        assert!(matches!(joined.as_slice(), [StratumMessage::Submit(_, _, _, joined_proof, ..)] if *joined_proof == proof));
+
+        //the fields of a chunk alone exceed the frame
+        let long_name = StratumMessage::Submit(Id::Num(1), "p".repeat(2000), String::from("42"), proof, 1, 120, 0, None);
+        assert!(split_submit(long_name, 1024).is_err());
+    }
+
+    #[test]
+    fn reject_bad_chunks() {
+        let proof = "abcdef".repeat(100);
+        let mut chunks = split_submit(submit(&proof), 256).unwrap();
+        assert!(chunks.len() > 2);
+        let mut assembler = ChunkAssembler::default();
+        assert!(assembler.push(chunks.remove(0)).unwrap().is_none());
+        //the second part is missing
+        assert!(assembler.push(chunks.remove(1)).is_err());
+
+        let mut chunks = split_submit(submit(&proof), 256).unwrap();
+        if let StratumMessage::SubmitChunk(_, _, _, part, ..) = &mut chunks[0] {
+            part.push('x');
+        }
+        assert!(assembler.push(chunks.remove(0)).is_err());
+    }
+}
@@ -9,20 +9,34 @@ use serde_json::Value;
 use tokio_util::codec::{AnyDelimiterCodec, Decoder, Encoder};
 use tracing::debug;
 
-use crate::message::StratumMessage;
+use crate::message::{HeartbeatStatus, StratumMessage};
+
+// CHANGE(zkpool): longest line accepted by StratumCodec::default, larger submits go in chunks
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 4096;
 
 pub struct StratumCodec {
     codec: AnyDelimiterCodec,
+    max_frame_length: usize,
 }
 
-impl Default for StratumCodec {
-    fn default() -> Self {
+impl StratumCodec {
+    // CHANGE(zkpool): set the longest line accepted per connection
+    pub fn new(max_frame_length: usize) -> Self {
         Self {
-            // Notify is ~400 bytes and submit is ~1750 bytes. 4096 should be enough for all messages
-            // TODO: verify again
-            codec: AnyDelimiterCodec::new_with_max_length(vec![b'\n'], vec![b'\n'], 4096),
+            codec: AnyDelimiterCodec::new_with_max_length(vec![b'\n'], vec![b'\n'], max_frame_length),
+            max_frame_length,
         }
     }
+
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+}
+
+impl Default for StratumCodec {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_FRAME_LENGTH)
+    }
 }
 
 // CHANGE(zkpool): use custom StratumMessage type
@@ -32,12 +46,24 @@ struct NotifyParams(String,u64,String,u64);
 #[derive(Serialize, Deserialize)]
 struct SubmitParams(String,String, String,u8,u32,u8);
 
+#[derive(Serialize, Deserialize)]
+struct SubmitReasonParams(String,String, String,u8,u32,u8,String);
+
+#[derive(Serialize, Deserialize)]
+struct SubmitChunkParams(String,String, String,u8,u32,u8,u32,u32,String);
+
 #[derive(Serialize, Deserialize)]
 struct HeartBeatParams(String, String);
 
+#[derive(Serialize, Deserialize)]
+struct HeartBeatStatusParams(String, String, HeartbeatStatus);
+
 #[derive(Serialize, Deserialize)]
 struct SubscribeParams(String, String,u64,u64,u64);
 
+#[derive(Serialize, Deserialize)]
+struct SubscribeCapabilityParams(String, String,u64,u64,u64,Vec<String>);
+
 pub trait BoxedType: ErasedSerialize + Send + DowncastSync {}
 erased_serde::serialize_trait_object!(BoxedType);
 impl_downcast!(sync BoxedType);
@@ -45,6 +71,7 @@ impl_downcast!(sync BoxedType);
 impl BoxedType for String {}
 impl BoxedType for Option<u64> {}
 impl BoxedType for Option<String> {}
+impl BoxedType for Vec<String> {}
 
 pub enum ResponseParams {
     Bool(bool),
@@ -52,6 +79,17 @@ pub enum ResponseParams {
     Null,
 }
 
+impl ResponseParams {
+    // CHANGE(zkpool): a pool supporting capabilities lists them in its zkpool.subscribe result,
+    // as the first list of strings in the result array
+    pub fn capabilities(&self) -> Option<Vec<String>> {
+        match self {
+            ResponseParams::Array(items) => items.iter().find_map(|item| item.downcast_ref::<Vec<String>>().cloned()),
+            _ => None,
+        }
+    }
+}
+
 impl Serialize for ResponseParams {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -85,6 +123,10 @@ impl<'de> Deserialize<'de> for ResponseParams {
                     Value::Null => vec.push(Box::new(None::<String>)),
                     Value::String(s) => vec.push(Box::new(s.clone())),
                     Value::Number(n) => vec.push(Box::new(n.as_u64())),
+                    // CHANGE(zkpool): keep nested lists of strings, such as the capabilities
+                    Value::Array(items) => vec.push(Box::new(
+                        items.iter().filter_map(|item| item.as_str().map(String::from)).collect::<Vec<String>>(),
+                    )),
                     _ => {}
                 });
                 Ok(ResponseParams::Array(vec))
@@ -101,7 +143,7 @@ impl Encoder<StratumMessage> for StratumCodec {
 
     fn encode(&mut self, item: StratumMessage, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let bytes = match item {
-            StratumMessage::Subscribe(id, user_agent, protocol_version, machine_cpu_num,machine_gpu_num,machine_mem) => {
+            StratumMessage::Subscribe(id, user_agent, protocol_version, machine_cpu_num,machine_gpu_num,machine_mem,capabilities) if capabilities.is_empty() => {
                 let request = Request {
                     jsonrpc: Version::V2,
                     method: "zkpool.subscribe",
@@ -110,6 +152,15 @@ impl Encoder<StratumMessage> for StratumCodec {
                 };
                 serde_json::to_vec(&request).unwrap_or_default()
             }
+            StratumMessage::Subscribe(id, user_agent, protocol_version, machine_cpu_num,machine_gpu_num,machine_mem,capabilities) => {
+                let request = Request {
+                    jsonrpc: Version::V2,
+                    method: "zkpool.subscribe",
+                    params: Some(SubscribeCapabilityParams(user_agent, protocol_version,machine_cpu_num,machine_gpu_num,machine_mem,capabilities)),
+                    id: Some(id),
+                };
+                serde_json::to_vec(&request).unwrap_or_default()
+            }
             StratumMessage::Authorize(id, worker_name, worker_password) => {
                 let request = Request {
                     jsonrpc: Version::V2,
@@ -129,7 +180,7 @@ impl Encoder<StratumMessage> for StratumCodec {
                 };
                 serde_json::to_vec(&request).unwrap_or_default()
             }
-            StratumMessage::Heartbeat(id, project_name,block) => {
+            StratumMessage::Heartbeat(id, project_name,block,None) => {
                 let request = Request {
                     jsonrpc: Version::V2,
                     method: "zkpool.heartbeat",
@@ -138,7 +189,17 @@ impl Encoder<StratumMessage> for StratumCodec {
                 };
                 serde_json::to_vec(&request).unwrap_or_default()
             }
-            StratumMessage::Submit(id, project_name,block, proof,degree,time,status) => {
+            // CHANGE(zkpool): the status goes as a third param so pools reading two params still work
+            StratumMessage::Heartbeat(id, project_name,block,Some(status)) => {
+                let request = Request {
+                    jsonrpc: Version::V2,
+                    method: "zkpool.heartbeat",
+                    params: Some(HeartBeatStatusParams(project_name,block,status)),
+                    id: Some(id),
+                };
+                serde_json::to_vec(&request).unwrap_or_default()
+            }
+            StratumMessage::Submit(id, project_name,block, proof,degree,time,status,None) => {
                 let request = Request {
                     jsonrpc: Version::V2,
                     method: "zkpool.submit",
@@ -147,7 +208,31 @@ impl Encoder<StratumMessage> for StratumCodec {
                 };
                 serde_json::to_vec(&request).unwrap_or_default()
             }
-            StratumMessage::Response(id, result, _) =>  {
+            // CHANGE(zkpool): the reason goes as a seventh param, only when set
+            StratumMessage::Submit(id, project_name,block, proof,degree,time,status,Some(reason)) => {
+                let request = Request {
+                    jsonrpc: Version::V2,
+                    method: "zkpool.submit",
+                    params: Some(SubmitReasonParams(project_name,block, proof,degree,time,status,reason)),
+                    id: Some(id),
+                };
+                serde_json::to_vec(&request).unwrap_or_default()
+            }
+            StratumMessage::SubmitChunk(id, project_name,block, part,degree,time,status,index,total,checksum) => {
+                let request = Request {
+                    jsonrpc: Version::V2,
+                    method: "zkpool.submit_chunk",
+                    params: Some(SubmitChunkParams(project_name,block, part,degree,time,status,index,total,checksum)),
+                    id: Some(id),
+                };
+                serde_json::to_vec(&request).unwrap_or_default()
+            }
+            // CHANGE(zkpool): send the error instead of a null result
+            StratumMessage::Response(id, _, Some(error)) =>  {
+                let response = Response::<Option<ResponseParams>, ()>::error(Version::V2, error, Some(id));
+                serde_json::to_vec(&response).unwrap_or_default()
+            },
+            StratumMessage::Response(id, result, None) =>  {
                 let response = Response::<Option<ResponseParams>, ()>::result(Version::V2, result, Some(id));
                 serde_json::to_vec(&response).unwrap_or_default()
             },
@@ -178,7 +263,25 @@ fn unwrap_u64_value(value: &Value) -> Result<u64, io::Error> {
     }
 }
 
-// CHANGE(zkpool): use custom StratumMessage protocol and name 
+// CHANGE(zkpool): pools send some numbers as decimal strings
+fn unwrap_u64_or_str_value(value: &Value) -> Result<u64, io::Error> {
+    match value {
+        Value::String(s) => s
+            .parse::<u64>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Param is not u64")),
+        _ => unwrap_u64_value(value),
+    }
+}
+
+// CHANGE(zkpool): reject out of range numbers instead of truncating them
+fn unwrap_narrow_value<T: TryFrom<u64>>(value: &Value) -> Result<T, io::Error> {
+    T::try_from(unwrap_u64_value(value)?).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Param is out of range"))
+}
+
+/// Room for the message of a json-rpc error
+const ERROR_MESSAGE_LENGTH: usize = 31;
+
+// CHANGE(zkpool): use custom StratumMessage protocol and name, every malformed message is an io::Error
 impl Decoder for StratumCodec {
     type Error = io::Error;
     type Item = StratumMessage;
@@ -188,21 +291,31 @@ impl Decoder for StratumCodec {
             .codec
             .decode(src)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-        if string.is_none() {
-            return Ok(None);
-        }
-        let bytes = string.unwrap();
-        let mut json = serde_json::from_slice::<serde_json::Value>(&bytes)
+        let bytes = match string {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+        let json = serde_json::from_slice::<serde_json::Value>(&bytes)
             .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
-        if !json.is_object() {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an object"));
-        }
-        let mut object = json.as_object().unwrap().clone();
-        object.remove("error");
-        json = object.clone().into();
-        if !json.is_object() {
-            return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an object"));
+        let mut object = match json {
+            Value::Object(object) => object,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "Not an object")),
+        };
+        // CHANGE(zkpool): keep a real error payload so callers can tell a rejected request, drop `"error": null`
+        if object.get("error").map_or(false, |error| !error.is_null()) {
+            object.remove("result");
+            // CHANGE(zkpool): json-rpc-types holds 31 bytes of message, cut longer ones instead of rejecting the response
+            if let Some(Value::String(message)) = object.get_mut("error").and_then(|error| error.get_mut("message")) {
+                let mut end = message.len().min(ERROR_MESSAGE_LENGTH);
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                message.truncate(end);
+            }
+        } else {
+            object.remove("error");
         }
+        let json: Value = object.clone().into();
         debug!(" help debug :New json with no error: {}", json.to_string());
         
         let result = if object.contains_key("method") {
@@ -215,15 +328,21 @@ impl Decoder for StratumCodec {
                 None => return Err(io::Error::new(io::ErrorKind::InvalidData, "No params")),
             };
             match method {
+                // CHANGE(zkpool): the capability list is optional
                 "zkpool.subscribe" => {
-                    if params.len() != 5 {
+                    if params.len() != 5 && params.len() != 6 {
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
                     }
                     let user_agent = unwrap_str_value(&params[0])?;
                     let protocol_version = unwrap_str_value(&params[1])?;
-                    let machine_cpu_num = params[2].as_u64().unwrap();
-                    let machine_gpu_num = params[3].as_u64().unwrap();
-                    let machine_mem = params[4].as_u64().unwrap();
+                    let machine_cpu_num = unwrap_u64_value(&params[2])?;
+                    let machine_gpu_num = unwrap_u64_value(&params[3])?;
+                    let machine_mem = unwrap_u64_value(&params[4])?;
+                    let capabilities = match params.get(5) {
+                        Some(value) => serde_json::from_value::<Vec<String>>(value.clone())
+                            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+                        None => Vec::new(),
+                    };
                     StratumMessage::Subscribe(
                         id.unwrap_or(Id::Num(0)),
                         user_agent,
@@ -231,23 +350,36 @@ impl Decoder for StratumCodec {
                         machine_cpu_num,
                         machine_gpu_num,
                         machine_mem,
+                        capabilities,
                     )
                 }
+                // CHANGE(zkpool): the access key and device id, a third param is ignored
                 "zkpool.authorize" => {
-                    if params.len() != 3 {
+                    if params.len() != 2 && params.len() != 3 {
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
                     }
                     let worker_name = unwrap_str_value(&params[0])?;
                     let worker_password = unwrap_str_value(&params[1])?;
                     StratumMessage::Authorize(id.unwrap_or(Id::Num(0)), worker_name, worker_password)
                 }
+                // CHANGE(zkpool): accept the two-field heartbeat and the one with a status
                 "zkpool.heartbeat" => {
-                    if params.len() != 1 {
+                    if params.len() != 2 && params.len() != 3 {
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
                     }
                     let project_name = unwrap_str_value(&params[0])?;
-                    let block = unwrap_u64_value(&params[1])?;
-                    StratumMessage::Heartbeat(id.unwrap_or(Id::Num(0)), project_name,block.to_string())
+                    let block = match &params[1] {
+                        Value::Number(_) => unwrap_u64_value(&params[1])?.to_string(),
+                        value => unwrap_str_value(value)?,
+                    };
+                    let status = match params.get(2) {
+                        Some(value) => Some(
+                            serde_json::from_value::<HeartbeatStatus>(value.clone())
+                                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+                        ),
+                        None => None,
+                    };
+                    StratumMessage::Heartbeat(id.unwrap_or(Id::Num(0)), project_name,block,status)
                 }
 
                 "zkpool.notify" => {
@@ -255,29 +387,56 @@ impl Decoder for StratumCodec {
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
                     }
                     let project_name = unwrap_str_value(&params[0])?;
-                    // let task_id = unwrap_u64_value(&params[1])?;
-                    let task_id =  (unwrap_str_value(&params[1])?).parse::<u64>().unwrap();
+                    let task_id = unwrap_u64_or_str_value(&params[1])?;
                     let task_content = unwrap_str_value(&params[2])?;
-                    // let degree = unwrap_u64_value(&params[3])?;
-                    let degree =  (unwrap_str_value(&params[3])?).parse::<u64>().unwrap();
-                    StratumMessage::Notify(id.unwrap(),project_name,task_id,task_content,degree)
+                    let degree = unwrap_u64_or_str_value(&params[3])?;
+                    StratumMessage::Notify(id.unwrap_or(Id::Num(0)),project_name,task_id,task_content,degree)
                 }
                 "zkpool.submit" => {
-                    if params.len() != 6 { 
+                    if params.len() != 6 && params.len() != 7 {
                         return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
                     }
                     let project_name = unwrap_str_value(&params[0])?;
                     let block = unwrap_str_value(&params[1])?;
                     let proof = unwrap_str_value(&params[2])?;
-                    let degree = unwrap_u64_value(&params[3])? as u8;
-                    let time = unwrap_u64_value(&params[4])?;
-                    let status = unwrap_u64_value(&params[5])? as u8;
-                    StratumMessage::Submit(id.unwrap_or(Id::Num(0)), project_name,block, proof,degree,time as u32,status)
+                    let degree = unwrap_narrow_value::<u8>(&params[3])?;
+                    let time = unwrap_narrow_value::<u32>(&params[4])?;
+                    let status = unwrap_narrow_value::<u8>(&params[5])?;
+                    let reason = match params.get(6) {
+                        Some(reason) => Some(unwrap_str_value(reason)?),
+                        None => None,
+                    };
+                    StratumMessage::Submit(id.unwrap_or(Id::Num(0)), project_name,block, proof,degree,time,status,reason)
+                }
+                "zkpool.submit_chunk" => {
+                    if params.len() != 9 {
+                        return Err(io::Error::new(io::ErrorKind::InvalidData, "Invalid params"));
+                    }
+                    let project_name = unwrap_str_value(&params[0])?;
+                    let block = unwrap_str_value(&params[1])?;
+                    let part = unwrap_str_value(&params[2])?;
+                    let degree = unwrap_narrow_value::<u8>(&params[3])?;
+                    let time = unwrap_narrow_value::<u32>(&params[4])?;
+                    let status = unwrap_narrow_value::<u8>(&params[5])?;
+                    let index = unwrap_narrow_value::<u32>(&params[6])?;
+                    let total = unwrap_narrow_value::<u32>(&params[7])?;
+                    let checksum = unwrap_str_value(&params[8])?;
+                    StratumMessage::SubmitChunk(id.unwrap_or(Id::Num(0)), project_name,block, part,degree,time,status,index,total,checksum)
                 }
                 _ => {
                     return Err(io::Error::new(io::ErrorKind::InvalidData, "Unknown method"));
                 }
             }
+        } else if object.get("result").map_or(false, Value::is_null) {
+            // CHANGE(zkpool): json-rpc-types takes a null result for a missing one, keep it as a null result
+            let id = match object.get("id") {
+                Some(id) => Some(
+                    serde_json::from_value::<Id>(id.clone())
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?,
+                ),
+                None => None,
+            };
+            StratumMessage::Response(id.unwrap_or(Id::Num(0)), Some(ResponseParams::Null), None)
         } else {
             let response = serde_json::from_value::<Response<ResponseParams, ()>>(json)
                 .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
@@ -290,3 +449,91 @@ impl Decoder for StratumCodec {
         Ok(Some(result))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_line(line: &str) -> StratumMessage {
+        let mut src = BytesMut::from(format!("{}\n", line).as_str());
+        StratumCodec::default().decode(&mut src).unwrap().unwrap()
+    }
+
+    #[test]
+    fn heartbeat_with_status() {
+        let status = HeartbeatStatus {
+            phase: String::from("proving"),
+            elapsed_secs: 120,
+            remaining_secs: Some(480),
+            cpu_load: Some(7.5),
+            free_memory_mb: Some(20480),
+            active_slots: 2,
+        };
+        let mut dst = BytesMut::new();
+        StratumCodec::default()
+            .encode(StratumMessage::Heartbeat(Id::Num(7), String::from("taikoA5"), String::from("42"), Some(status.clone())), &mut dst)
+            .unwrap();
+        match StratumCodec::default().decode(&mut dst).unwrap().unwrap() {
+            StratumMessage::Heartbeat(Id::Num(7), project_name, block, Some(decoded)) => {
+                assert_eq!((project_name.as_str(), block.as_str()), ("taikoA5", "42"));
+                assert_eq!(decoded, status);
+            }
+            message => panic!("unexpected {}", message.name()),
+        }
+    }
+
+    #[test]
+    fn subscribe_with_capabilities() {
+        let mut dst = BytesMut::new();
+        let capabilities = vec![String::from("progress"), String::from("json-task")];
+        StratumCodec::default()
+            .encode(StratumMessage::Subscribe(Id::Num(1), String::from("zkpool-prover/0.0.1"), String::from("ZkPoolStratum/1.0.0"), 16, 0, 64, capabilities.clone()), &mut dst)
+            .unwrap();
+        match StratumCodec::default().decode(&mut dst).unwrap().unwrap() {
+            StratumMessage::Subscribe(_, _, _, 16, 0, 64, decoded) => assert_eq!(decoded, capabilities),
+            message => panic!("unexpected {}", message.name()),
+        }
+
+        //a legacy subscribe has five params
+        match decode_line(r#"{"jsonrpc":"2.0","method":"zkpool.subscribe","params":["test","test",2,4,6],"id":1}"#) {
+            StratumMessage::Subscribe(_, _, _, 2, 4, 6, decoded) => assert!(decoded.is_empty()),
+            message => panic!("unexpected {}", message.name()),
+        }
+
+        match decode_line(r#"{"jsonrpc":"2.0","result":["session",["progress","cancel"]],"id":1,"error":null}"#) {
+            StratumMessage::Response(_, Some(result), None) => {
+                assert_eq!(result.capabilities(), Some(vec![String::from("progress"), String::from("cancel")]));
+            }
+            message => panic!("unexpected {}", message.name()),
+        }
+        match decode_line(r#"{"jsonrpc":"2.0","result":true,"id":1,"error":null}"#) {
+            StratumMessage::Response(_, Some(result), None) => assert!(result.capabilities().is_none()),
+            message => panic!("unexpected {}", message.name()),
+        }
+        match decode_line(r#"{"jsonrpc":"2.0","error":{"code":-32000,"message":"task 12 is not assigned to you, skip it"},"id":4}"#) {
+            StratumMessage::Response(Id::Num(4), None, Some(error)) => {
+                assert_eq!(error.message.as_str(), "task 12 is not assigned to you,");
+            }
+            message => panic!("unexpected {}", message.name()),
+        }
+        match decode_line(r#"{"jsonrpc":"2.0","result":null,"id":3}"#) {
+            StratumMessage::Response(Id::Num(3), Some(ResponseParams::Null), None) => {}
+            message => panic!("unexpected {}", message.name()),
+        }
+    }
+
+    #[test]
+    fn two_field_heartbeat() {
+        for line in [
+            r#"{"jsonrpc":"2.0","method":"zkpool.heartbeat","params":["taikoA5","42"],"id":3}"#,
+            r#"{"jsonrpc":"2.0","method":"zkpool.heartbeat","params":["taikoA5",42],"id":3}"#,
+        ] {
+            match decode_line(line) {
+                StratumMessage::Heartbeat(_, project_name, block, None) => {
+                    assert_eq!((project_name.as_str(), block.as_str()), ("taikoA5", "42"));
+                }
+                message => panic!("unexpected {}", message.name()),
+            }
+        }
+    }
+}
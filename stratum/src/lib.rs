@@ -1,2 +1,4 @@
+pub mod chunk;
 pub mod codec;
 pub mod message;
+pub mod proof;
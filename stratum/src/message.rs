@@ -1,23 +1,94 @@
 use json_rpc_types::{Error, Id};
+use serde::{Deserialize, Serialize};
 
 use crate::codec::ResponseParams;
 
+// CHANGE(zkpool): protocol version sent in zkpool.subscribe
+pub const PROTOCOL_VERSION: &str = "ZkPoolStratum/1.0.0";
+
+// CHANGE(zkpool): optional protocol features, used only if both sides list them during zkpool.subscribe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Capability {
+    /// heartbeats carry a HeartbeatStatus
+    Progress,
+    /// the pool may cancel a task
+    Cancel,
+    /// proofs too large for one frame are submitted in chunks
+    ChunkedSubmit,
+    /// task content is a JSON document
+    JsonTask,
+    /// proofs are submitted in the versioned format of the proof module
+    ProofV1,
+    /// proofs failing the prover's checks are submitted with status 2 and the reason
+    InvalidProof,
+}
+
+impl Capability {
+    pub const ALL: [Capability; 6] = [
+        Capability::Progress,
+        Capability::Cancel,
+        Capability::ChunkedSubmit,
+        Capability::JsonTask,
+        Capability::ProofV1,
+        Capability::InvalidProof,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Capability::Progress => "progress",
+            Capability::Cancel => "cancel",
+            Capability::ChunkedSubmit => "chunked-submit",
+            Capability::JsonTask => "json-task",
+            Capability::ProofV1 => "proof-v1",
+            Capability::InvalidProof => "invalid-proof",
+        }
+    }
+
+    /// None for capabilities of a newer peer
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|capability| capability.name() == name)
+    }
+}
+
 // CHANGE(zkpool): use custom StratumMessage protocol
 pub enum StratumMessage {
   
-    Subscribe(Id, String, String, u64, u64, u64),
+    // CHANGE(zkpool): capability names offered by the prover, sent as a sixth param unless empty
+    Subscribe(Id, String, String, u64, u64, u64, Vec<String>),
 
     Authorize(Id, String, String),
 
     Notify(Id,String,u64,String,u64),
 
-    Heartbeat(Id,String,String),
+    // CHANGE(zkpool): the status is None for provers sending the two-field heartbeat
+    Heartbeat(Id,String,String,Option<HeartbeatStatus>),
 
-    Submit(Id, String,String, String,u8,u32,u8),
+    // CHANGE(zkpool): the reason is set for proofs that are not submitted as computed, e.g. failing local verification
+    Submit(Id, String,String, String,u8,u32,u8,Option<String>),
+
+    // CHANGE(zkpool): one part of a submit too large for a frame: the submit fields with a part of the proof,
+    // then the part index, the number of parts and the sha256 hex of the part
+    SubmitChunk(Id, String, String, String, u8, u32, u8, u32, u32, String),
 
     Response(Id, Option<ResponseParams>, Option<Error<()>>),
 }
 
+// CHANGE(zkpool): task progress and host load reported with a heartbeat
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HeartbeatStatus {
+    /// idle, queued, proving or submitting
+    pub phase: String,
+    /// seconds spent in the current phase
+    pub elapsed_secs: u64,
+    /// estimated seconds until the proof is ready, None if unknown
+    pub remaining_secs: Option<u64>,
+    /// 1 minute load average of the host
+    pub cpu_load: Option<f64>,
+    pub free_memory_mb: Option<u64>,
+    /// tasks being proven or submitted
+    pub active_slots: u64,
+}
+
 // CHANGE(zkpool): use custom StratumMessage name
 impl StratumMessage {
     pub fn name(&self) -> &'static str {
@@ -26,6 +97,7 @@ impl StratumMessage {
             StratumMessage::Authorize(..) => "zkpool.authorize",
             StratumMessage::Notify(..) => "zkpool.notify",
             StratumMessage::Submit(..) => "zkpool.submit",
+            StratumMessage::SubmitChunk(..) => "zkpool.submit_chunk",
             StratumMessage::Response(..) => "zkpool.response",
             StratumMessage::Heartbeat(..) => "zkpool.heartbeat",
         }
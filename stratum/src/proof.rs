@@ -0,0 +1,161 @@
+// CHANGE(zkpool): proof encoding for zkpool.submit. Version 1 is a JSON object with the instances and the
+// base64 proof, its length and sha256; the legacy `#instance...#0xproof` string stays for pools without proof-v1
+
+use std::io;
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+pub const PROOF_FORMAT_VERSION: u32 = 1;
+
+/// A proof with its public instances, in decimal.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Proof {
+    pub instances: Vec<String>,
+    pub proof: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofFormat {
+    Legacy,
+    V1,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EncodedProof {
+    version: u32,
+    instances: Vec<String>,
+    /// base64
+    proof: String,
+    /// bytes of the decoded proof
+    length: usize,
+    /// hex sha256 of the decoded proof
+    sha256: String,
+}
+
+fn invalid(reason: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason.to_string())
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(hex: &str) -> Result<Vec<u8>, io::Error> {
+    if hex.len() % 2 != 0 {
+        return Err(invalid("Odd hex length"));
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|byte| u8::from_str_radix(byte, 16).ok())
+                .ok_or_else(|| invalid("Invalid hex"))
+        })
+        .collect()
+}
+
+pub fn encode(proof: &Proof) -> String {
+    let encoded = EncodedProof {
+        version: PROOF_FORMAT_VERSION,
+        instances: proof.instances.clone(),
+        proof: STANDARD.encode(&proof.proof),
+        length: proof.proof.len(),
+        sha256: to_hex(&Sha256::digest(&proof.proof)),
+    };
+    serde_json::to_string(&encoded).unwrap_or_default()
+}
+
+/// Decode a version 1 proof, checking its length and hash.
+pub fn decode(encoded: &str) -> Result<Proof, io::Error> {
+    let encoded: EncodedProof = serde_json::from_str(encoded).map_err(|e| invalid(&e.to_string()))?;
+    if encoded.version != PROOF_FORMAT_VERSION {
+        return Err(invalid("Unsupported proof version"));
+    }
+    let proof = STANDARD.decode(&encoded.proof).map_err(|e| invalid(&e.to_string()))?;
+    if proof.len() != encoded.length {
+        return Err(invalid("Proof length mismatch"));
+    }
+    if to_hex(&Sha256::digest(&proof)) != encoded.sha256 {
+        return Err(invalid("Proof hash mismatch"));
+    }
+    Ok(Proof { instances: encoded.instances, proof })
+}
+
+/// Every instance then the proof in hex, each prefixed by `#`. An empty proof, as sent for failed tasks, is still `#0x`.
+pub fn encode_legacy(proof: &Proof) -> String {
+    let mut encoded = String::new();
+    for instance in &proof.instances {
+        encoded.push('#');
+        encoded.push_str(instance);
+    }
+    encoded.push_str("#0x");
+    encoded.push_str(&to_hex(&proof.proof));
+    encoded
+}
+
+pub fn decode_legacy(encoded: &str) -> Result<Proof, io::Error> {
+    let fields = encoded.strip_prefix('#').ok_or_else(|| invalid("Missing leading #"))?;
+    let mut fields: Vec<&str> = fields.split('#').collect();
+    let proof = fields.pop().unwrap_or_default();
+    let proof = from_hex(proof.strip_prefix("0x").unwrap_or(proof))?;
+    Ok(Proof { instances: fields.into_iter().map(String::from).collect(), proof })
+}
+
+/// Decode either format, version 1 proofs are JSON objects.
+pub fn decode_any(encoded: &str) -> Result<Proof, io::Error> {
+    if encoded.starts_with('{') {
+        decode(encoded)
+    } else {
+        decode_legacy(encoded)
+    }
+}
+
+/// Re-encode a proof in `format`.
+pub fn convert(encoded: &str, format: ProofFormat) -> Result<String, io::Error> {
+    let proof = decode_any(encoded)?;
+    Ok(match format {
+        ProofFormat::Legacy => encode_legacy(&proof),
+        ProofFormat::V1 => encode(&proof),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proof() -> Proof {
+        Proof {
+            instances: vec![String::from("12345678901234567890"), String::from("0")],
+            proof: vec![0x00, 0x01, 0xab, 0xff],
+        }
+    }
+
+    const GOLDEN_V1: &str = r#"{"version":1,"instances":["12345678901234567890","0"],"proof":"AAGr/w==","length":4,"sha256":"1cde933895cd9b83f478c84fcbcd5ebc1c8409bdc219e82b437cd5a8532ff41d"}"#;
+    const GOLDEN_LEGACY: &str = "#12345678901234567890#0#0x0001abff";
+
+    #[test]
+    fn golden_encodings() {
+        assert_eq!(encode(&proof()), GOLDEN_V1);
+        assert_eq!(encode_legacy(&proof()), GOLDEN_LEGACY);
+        assert_eq!(decode(GOLDEN_V1).unwrap(), proof());
+        assert_eq!(decode_legacy(GOLDEN_LEGACY).unwrap(), proof());
+        assert_eq!(convert(GOLDEN_LEGACY, ProofFormat::V1).unwrap(), GOLDEN_V1);
+        assert_eq!(convert(GOLDEN_V1, ProofFormat::Legacy).unwrap(), GOLDEN_LEGACY);
+
+        //failed tasks submit an empty proof
+        assert_eq!(encode_legacy(&Proof::default()), "#0x");
+        assert_eq!(decode_any("#0x").unwrap(), Proof::default());
+        assert_eq!(decode_any("#").unwrap(), Proof::default());
+    }
+
+    #[test]
+    fn reject_corrupted_proofs() {
+        assert!(decode(&GOLDEN_V1.replace("AAGr/w==", "AAGr/g==")).is_err());
+        assert!(decode(&GOLDEN_V1.replace("\"length\":4", "\"length\":5")).is_err());
+        assert!(decode(&GOLDEN_V1.replace("\"version\":1", "\"version\":2")).is_err());
+        assert!(decode_legacy("#1#0xabc").is_err());
+        assert!(decode_legacy("1#0xab").is_err());
+    }
+}
@@ -0,0 +1,182 @@
+use bytes::BytesMut;
+use json_rpc_types::{Error, ErrorCode, Id};
+use proptest::{collection::vec, option, prelude::*};
+use taiko_stratum::{
+    codec::{BoxedType, ResponseParams, StratumCodec},
+    message::{HeartbeatStatus, StratumMessage},
+};
+use tokio_util::codec::{Decoder, Encoder};
+
+fn encode(message: StratumMessage) -> BytesMut {
+    let mut dst = BytesMut::new();
+    StratumCodec::default().encode(message, &mut dst).unwrap();
+    dst
+}
+
+fn text() -> impl Strategy<Value = String> {
+    "[a-zA-Z0-9 #_.\"\\\\\n/-]{0,64}"
+}
+
+fn id() -> impl Strategy<Value = Id> {
+    any::<u64>().prop_map(Id::Num)
+}
+
+fn status() -> impl Strategy<Value = HeartbeatStatus> {
+    (
+        prop_oneof![Just("idle"), Just("queued"), Just("proving"), Just("submitting")],
+        any::<u64>(),
+        option::of(any::<u64>()),
+        option::of((0u32..100_000).prop_map(|load| load as f64 / 100.0)),
+        option::of(any::<u64>()),
+        any::<u64>(),
+    )
+        .prop_map(|(phase, elapsed_secs, remaining_secs, cpu_load, free_memory_mb, active_slots)| HeartbeatStatus {
+            phase: phase.to_string(),
+            elapsed_secs,
+            remaining_secs,
+            cpu_load,
+            free_memory_mb,
+            active_slots,
+        })
+}
+
+#[derive(Debug, Clone)]
+enum Item {
+    Str(String),
+    Num(Option<u64>),
+    List(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+enum Params {
+    Null,
+    Bool(bool),
+    Array(Vec<Item>),
+}
+
+/// A message to generate, StratumMessage itself is neither Debug nor Clone.
+#[derive(Debug, Clone)]
+enum Spec {
+    Subscribe(Id, String, String, u64, u64, u64, Vec<String>),
+    Authorize(Id, String, String),
+    Notify(String, u64, String, u64),
+    Heartbeat(Id, String, String, Option<HeartbeatStatus>),
+    Submit(Id, String, String, String, u8, u32, u8, Option<String>),
+    SubmitChunk(Id, String, String, String, u8, u32, u8, u32, u32, String),
+    Response(Id, Option<Params>, Option<Error<()>>),
+}
+
+impl Spec {
+    fn build(self) -> StratumMessage {
+        match self {
+            Spec::Subscribe(id, user_agent, protocol_version, cpu, gpu, memory, capabilities) => {
+                StratumMessage::Subscribe(id, user_agent, protocol_version, cpu, gpu, memory, capabilities)
+            }
+            Spec::Authorize(id, name, password) => StratumMessage::Authorize(id, name, password),
+            //notify is sent without an id
+            Spec::Notify(project, task_id, content, degree) => StratumMessage::Notify(Id::Num(0), project, task_id, content, degree),
+            Spec::Heartbeat(id, project, block, status) => StratumMessage::Heartbeat(id, project, block, status),
+            Spec::Submit(id, project, block, proof, degree, time, status, reason) => {
+                StratumMessage::Submit(id, project, block, proof, degree, time, status, reason)
+            }
+            Spec::SubmitChunk(id, project, block, part, degree, time, status, index, total, checksum) => {
+                StratumMessage::SubmitChunk(id, project, block, part, degree, time, status, index, total, checksum)
+            }
+            Spec::Response(id, result, error) => {
+                let result = result.map(|params| match params {
+                    Params::Null => ResponseParams::Null,
+                    Params::Bool(b) => ResponseParams::Bool(b),
+                    Params::Array(items) => ResponseParams::Array(
+                        items
+                            .into_iter()
+                            .map(|item| -> Box<dyn BoxedType> {
+                                match item {
+                                    Item::Str(s) => Box::new(s),
+                                    Item::Num(n) => Box::new(n),
+                                    Item::List(l) => Box::new(l),
+                                }
+                            })
+                            .collect(),
+                    ),
+                });
+                StratumMessage::Response(id, result, error)
+            }
+        }
+    }
+}
+
+fn params() -> impl Strategy<Value = Params> {
+    let item = prop_oneof![
+        text().prop_map(Item::Str),
+        option::of(any::<u64>()).prop_map(Item::Num),
+        vec(text(), 0..4).prop_map(Item::List),
+    ];
+    prop_oneof![
+        Just(Params::Null),
+        any::<bool>().prop_map(Params::Bool),
+        vec(item, 0..4).prop_map(Params::Array),
+    ]
+}
+
+fn error() -> impl Strategy<Value = Error<()>> {
+    //json-rpc-types keeps short error messages only
+    (-32768i64..32768, "[a-z ]{1,30}").prop_map(|(code, message)| Error::with_custom_msg(ErrorCode::from_code(code), &message))
+}
+
+fn spec() -> impl Strategy<Value = Spec> {
+    prop_oneof![
+        (id(), text(), text(), any::<u64>(), any::<u64>(), any::<u64>(), vec(text(), 0..4)).prop_map(
+            |(id, user_agent, protocol_version, cpu, gpu, memory, capabilities)| {
+                Spec::Subscribe(id, user_agent, protocol_version, cpu, gpu, memory, capabilities)
+            }
+        ),
+        (id(), text(), text()).prop_map(|(id, name, password)| Spec::Authorize(id, name, password)),
+        (text(), any::<u64>(), text(), any::<u64>())
+            .prop_map(|(project, task_id, content, degree)| Spec::Notify(project, task_id, content, degree)),
+        (id(), text(), text(), option::of(status()))
+            .prop_map(|(id, project, block, status)| Spec::Heartbeat(id, project, block, status)),
+        (id(), text(), text(), text(), any::<u8>(), any::<u32>(), any::<u8>(), option::of(text())).prop_map(
+            |(id, project, block, proof, degree, time, status, reason)| Spec::Submit(id, project, block, proof, degree, time, status, reason)
+        ),
+        (id(), text(), text(), text(), (any::<u8>(), any::<u32>(), any::<u8>()), any::<u32>(), any::<u32>(), "[0-9a-f]{64}").prop_map(
+            |(id, project, block, part, (degree, time, status), index, total, checksum)| {
+                Spec::SubmitChunk(id, project, block, part, degree, time, status, index, total, checksum)
+            }
+        ),
+        (id(), option::of(params()), option::of(error())).prop_map(|(id, result, error)| Spec::Response(id, result, error)),
+    ]
+}
+
+proptest! {
+    /// Every message decodes to one that encodes to the same bytes.
+    #[test]
+    fn encode_decode_roundtrip(spec in spec()) {
+        let message = spec.build();
+        let name = message.name();
+        let encoded = encode(message);
+        let mut src = encoded.clone();
+        let decoded = StratumCodec::default().decode(&mut src).unwrap().unwrap();
+        prop_assert_eq!(decoded.name(), name);
+        prop_assert_eq!(encode(decoded), encoded);
+        prop_assert!(src.is_empty());
+    }
+
+    /// Malformed lines are errors, not panics.
+    #[test]
+    fn decode_garbage(line in "[\\[\\]{}\":,0-9a-z. -]{0,256}") {
+        let mut src = BytesMut::from(format!("{}\n", line).as_str());
+        let _ = StratumCodec::default().decode(&mut src);
+    }
+
+    /// Valid requests with params of the wrong type or count are errors, not panics.
+    #[test]
+    fn decode_wrong_params(
+        method in prop_oneof![Just("subscribe"), Just("authorize"), Just("notify"), Just("heartbeat"), Just("submit")],
+        params in vec(prop_oneof![Just("null"), Just("-1"), Just("1.5"), Just("18446744073709551616"), Just("\"x\""), Just("\"7\""), Just("[]"), Just("{}")], 0..8),
+        with_id in any::<bool>(),
+    ) {
+        let id = if with_id { ",\"id\":1" } else { "" };
+        let line = format!("{{\"jsonrpc\":\"2.0\",\"method\":\"zkpool.{}\",\"params\":[{}]{}}}\n", method, params.join(","), id);
+        let _ = StratumCodec::default().decode(&mut BytesMut::from(line.as_str()));
+    }
+}